@@ -0,0 +1,17 @@
+use std::{env, ffi::OsString};
+
+/// The command line being completed, up to the word under the cursor
+///
+/// The shells pass the whole line after `--`, bash and zsh also tell which word is the current one.
+pub fn completed_words() -> Vec<OsString> {
+    let mut words = env::args_os()
+        .skip_while(|a| a != "--")
+        .skip(1)
+        .collect::<Vec<_>>();
+    let current = env::var("_CLAP_COMPLETE_INDEX")
+        .ok()
+        .and_then(|i| i.parse().ok())
+        .unwrap_or(words.len().saturating_sub(1));
+    words.truncate(current);
+    words
+}
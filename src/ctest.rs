@@ -0,0 +1,203 @@
+use std::{fs, io, path::Path, process::Command};
+
+use serde::Deserialize;
+
+/// Where CTest lists the tests that failed in its last run, relative to the build folder
+const LAST_FAILED_PATH: &str = "Testing/Temporary/LastTestsFailed.log";
+
+/// A test as registered with CTest
+pub struct TestInfo {
+    pub name: String,
+    pub labels: Vec<String>,
+    /// The program the test runs, if any
+    pub executable: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ShowOnly {
+    #[serde(default)]
+    tests: Vec<ShowOnlyTest>,
+}
+
+#[derive(Deserialize)]
+struct ShowOnlyTest {
+    name: String,
+    #[serde(default)]
+    command: Vec<String>,
+    #[serde(default)]
+    properties: Vec<Property>,
+}
+
+#[derive(Deserialize)]
+struct Property {
+    name: String,
+    value: serde_json::Value,
+}
+
+/// Lists the tests of a build folder without running them
+pub fn show_only_command(build_dir: &Path) -> Command {
+    let mut cmd = Command::new("ctest");
+    cmd.arg("--test-dir");
+    cmd.arg(build_dir);
+    cmd.arg("--show-only=json-v1");
+    cmd
+}
+
+/// Parse the output of `ctest --show-only=json-v1`
+pub fn parse_test_list(json: &str) -> Result<Vec<TestInfo>, String> {
+    let list = serde_json::from_str::<ShowOnly>(json)
+        .map_err(|e| format!("Could not parse the test list of ctest: {e}"))?;
+    Ok(list
+        .tests
+        .into_iter()
+        .map(|t| {
+            let labels = t
+                .properties
+                .iter()
+                .filter(|p| p.name == "LABELS")
+                .filter_map(|p| p.value.as_array())
+                .flatten()
+                .filter_map(|l| l.as_str().map(String::from))
+                .collect();
+            TestInfo {
+                name: t.name,
+                labels,
+                executable: executable(t.command),
+            }
+        })
+        .collect())
+}
+
+/// The tests that failed in the last CTest run of a build folder
+///
+/// The file has a `<number>:<name>` line per test, a missing file means there is nothing to rerun.
+pub fn last_failed(build_dir: &Path) -> io::Result<Vec<String>> {
+    let data = match fs::read_to_string(build_dir.join(LAST_FAILED_PATH)) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(data
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(_, name)| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect())
+}
+
+/// The program a test runs, looking through the `run_test.py` wrapper of ament
+///
+/// ament registers its tests as `python3 run_test.py <result file> ... --command <program> <args>`.
+fn executable(command: Vec<String>) -> Option<String> {
+    let wrapped = command.iter().any(|a| a.ends_with("run_test.py"));
+    let program = match command.iter().position(|a| a == "--command") {
+        Some(i) if wrapped => i + 1,
+        _ => 0,
+    };
+    command.into_iter().nth(program)
+}
+
+/// A regex for `ctest -R` which matches exactly the given tests
+pub fn name_regex(names: &[String]) -> String {
+    let escaped = names.iter().map(|n| escape(n)).collect::<Vec<_>>();
+    match escaped.as_slice() {
+        [single] => format!("^{single}$"),
+        _ => format!("^({})$", escaped.join("|")),
+    }
+}
+
+fn escape(name: &str) -> String {
+    let mut res = String::with_capacity(name.len());
+    for c in name.chars() {
+        if r"\.^$|()[]{}*+?".contains(c) {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_test_list() {
+        let json = r#"{"tests": [
+            {
+                "name": "test_node",
+                "command": ["/ws/build/pkg/test_node", "--gtest_output=xml:/tmp/x.xml"],
+                "properties": [{"name": "LABELS", "value": ["gtest", "unit"]}]
+            },
+            {"name": "cppcheck"}
+        ]}"#;
+        let tests = parse_test_list(json).unwrap();
+        assert_eq!(tests.len(), 2);
+        assert_eq!(tests[0].name, "test_node");
+        assert_eq!(tests[0].labels, ["gtest", "unit"]);
+        assert_eq!(
+            tests[0].executable.as_deref(),
+            Some("/ws/build/pkg/test_node")
+        );
+        assert!(tests[1].labels.is_empty());
+        assert_eq!(tests[1].executable, None);
+    }
+
+    #[test]
+    fn looks_through_ament_wrapper() {
+        let command = [
+            "/usr/bin/python3",
+            "-u",
+            "/opt/ros/humble/share/ament_cmake_test/cmake/run_test.py",
+            "/ws/build/pkg/test_results/pkg/test_node.gtest.xml",
+            "--package-name",
+            "pkg",
+            "--command",
+            "/ws/build/pkg/test_node",
+            "--gtest_output=xml:/ws/build/pkg/test_results/pkg/test_node.gtest.xml",
+        ]
+        .map(String::from)
+        .to_vec();
+        assert_eq!(
+            executable(command).as_deref(),
+            Some("/ws/build/pkg/test_node")
+        );
+    }
+
+    #[test]
+    fn keeps_unwrapped_commands() {
+        let command = ["/usr/bin/python3", "-m", "pytest", "--command", "x"]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(executable(command).as_deref(), Some("/usr/bin/python3"));
+        assert_eq!(executable(vec![]), None);
+    }
+
+    #[test]
+    fn matches_names_exactly() {
+        assert_eq!(name_regex(&["test_node".into()]), "^test_node$");
+        assert_eq!(
+            name_regex(&["test_a".into(), "test_b".into()]),
+            "^(test_a|test_b)$"
+        );
+    }
+
+    #[test]
+    fn escapes_regex_characters() {
+        assert_eq!(
+            name_regex(&["test_node.py[1]".into()]),
+            r"^test_node\.py\[1\]$"
+        );
+    }
+
+    #[test]
+    fn reads_last_failed_tests() {
+        let dir = std::env::temp_dir().join(format!("colb-{}-last-failed", std::process::id()));
+        let log = dir.join(LAST_FAILED_PATH);
+        fs::create_dir_all(log.parent().unwrap()).unwrap();
+        fs::write(&log, "3:test_a\n7:test_b\n\n").unwrap();
+        assert_eq!(last_failed(&dir).unwrap(), ["test_a", "test_b"]);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(last_failed(&dir).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,69 @@
+use toml_edit::{DocumentMut, Item, Table, Value};
+
+/// Set a dotted key in a configuration file, keeping its comments and formatting
+///
+/// Missing tables are created on the way, an existing value keeps its surrounding comments.
+pub fn set_value(data: &str, key: &str, value: Value) -> Result<String, String> {
+    let mut doc = data.parse::<DocumentMut>().map_err(|e| e.to_string())?;
+    let path = key.split('.').collect::<Vec<_>>();
+    let (name, parents) = path.split_last().ok_or("Missing key")?;
+    let mut table = doc.as_table_mut() as &mut dyn toml_edit::TableLike;
+    for (i, part) in parents.iter().enumerate() {
+        table = table
+            .entry(part)
+            .or_insert_with(|| {
+                // Tables which only contain other tables don't need a header
+                let mut t = Table::new();
+                t.set_implicit(true);
+                Item::Table(t)
+            })
+            .as_table_like_mut()
+            .ok_or_else(|| format!("'{}' is not a table", path[..=i].join(".")))?;
+    }
+    match table.get_mut(name) {
+        Some(Item::Value(existing)) => {
+            let decor = existing.decor().clone();
+            *existing = value;
+            *existing.decor_mut() = decor;
+        }
+        Some(Item::Table(_)) | Some(Item::ArrayOfTables(_)) => {
+            return Err(format!("'{key}' is a table"));
+        }
+        _ => {
+            table.insert(name, Item::Value(value));
+        }
+    }
+    Ok(doc.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_comments_when_replacing_a_value() {
+        let data = "# Shared settings\n[package]\n# Faster for development\nbuild_type = \"Debug\" # or Release\nparallel_jobs = 8\n";
+        let res = set_value(data, "package.build_type", "Release".into()).unwrap();
+        assert_eq!(
+            res,
+            "# Shared settings\n[package]\n# Faster for development\nbuild_type = \"Release\" # or Release\nparallel_jobs = 8\n"
+        );
+    }
+
+    #[test]
+    fn creates_missing_tables() {
+        let data = "version = 1\n\n[package] # active\nbuild_type = \"Debug\"\n";
+        let res = set_value(data, "packages.foo.parallel_jobs", 2.into()).unwrap();
+        assert_eq!(
+            res,
+            "version = 1\n\n[package] # active\nbuild_type = \"Debug\"\n\n[packages.foo]\nparallel_jobs = 2\n"
+        );
+    }
+
+    #[test]
+    fn refuses_to_replace_tables() {
+        let data = "[package]\nbuild_type = \"Debug\"\n";
+        assert!(set_value(data, "package", 1.into()).is_err());
+        assert!(set_value(data, "package.build_type.x", 1.into()).is_err());
+    }
+}
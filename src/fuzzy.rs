@@ -0,0 +1,51 @@
+/// How similar a candidate has to be to count as a match
+const THRESHOLD: f64 = 0.85;
+
+/// How many matches to show when there is no unique one
+const MAX_SHOWN: usize = 5;
+
+/// The candidates matching a misspelled or partial name, best matches first
+///
+/// Candidates containing the name count as matches, others have to be similar enough.
+pub fn matches<'a>(name: &str, candidates: &[&'a str]) -> Vec<&'a str> {
+    let name = name.to_lowercase();
+    let mut scored = candidates
+        .iter()
+        .filter_map(|c| {
+            let lower = c.to_lowercase();
+            // Prefer the shortest candidate containing the name
+            let score = if lower.contains(&name) {
+                2.0 - lower.len() as f64 / 1000.0
+            } else {
+                strsim::jaro_winkler(&name, &lower)
+            };
+            (score > THRESHOLD).then_some((score, *c))
+        })
+        .collect::<Vec<_>>();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(b.1)));
+    scored.into_iter().map(|(_, c)| c).collect()
+}
+
+/// The only match with `pick`, otherwise the closest matches for an error message
+pub fn resolve<'a>(
+    name: &str,
+    candidates: &[&'a str],
+    pick: bool,
+) -> Result<&'a str, Vec<&'a str>> {
+    match matches(name, candidates).as_slice() {
+        [unique] if pick => Ok(unique),
+        matches => Err(matches.to_vec()),
+    }
+}
+
+/// Suggests the matches at the end of a "not found" message
+pub fn hint(matches: &[&str]) -> String {
+    match matches {
+        [] => String::new(),
+        [unique] => format!(", did you mean '{unique}'? (Use --fuzzy to pick it automatically)"),
+        _ => {
+            let shown = matches.iter().take(MAX_SHOWN).copied().collect::<Vec<_>>();
+            format!(", did you mean one of: {}?", shown.join(", "))
+        }
+    }
+}
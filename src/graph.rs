@@ -0,0 +1,410 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+use crate::{package::DependencyKind, workspace::Workspace};
+
+/// The dependency categories colcon sorts manifest dependencies into
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Category {
+    Build,
+    Exec,
+    Test,
+}
+
+impl Category {
+    fn of(kind: DependencyKind) -> Option<Category> {
+        match kind {
+            DependencyKind::Build | DependencyKind::Buildtool => Some(Category::Build),
+            DependencyKind::BuildExport
+            | DependencyKind::BuildtoolExport
+            | DependencyKind::Exec => Some(Category::Exec),
+            DependencyKind::Test => Some(Category::Test),
+            DependencyKind::Doc => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Build => "build",
+            Category::Exec => "exec",
+            Category::Test => "test",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Link<'a> {
+    pub package: &'a str,
+    pub dependency: &'a str,
+    pub categories: &'a BTreeSet<Category>,
+}
+
+/// Renders a chain as `a -(build)-> b -(exec)-> c`
+pub fn format_chain(chain: &[Link]) -> String {
+    let mut res = chain
+        .first()
+        .map(|l| l.package.to_string())
+        .unwrap_or_default();
+    for link in chain {
+        let _ = write!(
+            res,
+            " -({})-> {}",
+            category_label(link.categories),
+            link.dependency
+        );
+    }
+    res
+}
+
+#[derive(clap::ValueEnum, Default, Clone)]
+pub enum GraphFormat {
+    #[default]
+    Dot,
+    Mermaid,
+}
+
+/// Dependencies between the packages of a workspace
+///
+/// Dependencies on packages outside of the workspace (e.g. from an underlay) are not part of the graph.
+pub struct DependencyGraph {
+    /// package -> dependency -> categories
+    edges: BTreeMap<String, BTreeMap<String, BTreeSet<Category>>>,
+}
+
+impl DependencyGraph {
+    pub fn new(workspace: &Workspace) -> DependencyGraph {
+        let mut edges = BTreeMap::new();
+        for (name, package) in &workspace.packages {
+            let deps: &mut BTreeMap<String, BTreeSet<Category>> =
+                edges.entry(name.clone()).or_default();
+            for dep in &package.manifest.dependencies {
+                if dep.name == *name || workspace.get(&dep.name).is_none() {
+                    continue;
+                }
+                if let Some(category) = Category::of(dep.kind) {
+                    deps.entry(dep.name.clone()).or_default().insert(category);
+                }
+            }
+        }
+        DependencyGraph { edges }
+    }
+
+    pub fn contains(&self, package: &str) -> bool {
+        self.edges.contains_key(package)
+    }
+
+    /// The direct dependencies of a package which colcon follows from there on
+    ///
+    /// Like `--packages-up-to`, all categories are considered for the selected package,
+    /// but only runtime dependencies for everything pulled in by it.
+    fn followed(
+        &self,
+        package: &str,
+        is_root: bool,
+    ) -> impl Iterator<Item = (&String, &BTreeSet<Category>)> {
+        self.edges
+            .get(package)
+            .into_iter()
+            .flatten()
+            .filter(move |(_, categories)| is_root || categories.contains(&Category::Exec))
+    }
+
+    /// The package and everything `--packages-up-to` would build for it
+    pub fn up_to(&self, package: &str) -> BTreeSet<String> {
+        let mut res = BTreeSet::from([package.to_string()]);
+        let mut queue = vec![(package.to_string(), true)];
+        while let Some((current, is_root)) = queue.pop() {
+            for (dep, _) in self.followed(&current, is_root) {
+                if res.insert(dep.clone()) {
+                    queue.push((dep.clone(), false));
+                }
+            }
+        }
+        res
+    }
+
+    /// Everything `--packages-above <package>` would build, except for the package itself
+    pub fn dependents(&self, package: &str) -> BTreeSet<String> {
+        self.edges
+            .keys()
+            .filter(|p| *p != package && self.up_to(p).contains(package))
+            .cloned()
+            .collect()
+    }
+
+    /// Every shortest dependency chain along which `--packages-up-to <package>` pulls in `dependency`
+    ///
+    /// Each chain is a list of links, starting at `package` and ending at `dependency`.
+    pub fn shortest_chains(&self, package: &str, dependency: &str) -> Vec<Vec<Link<'_>>> {
+        let Some((root, _)) = self.edges.get_key_value(package) else {
+            return vec![];
+        };
+        let mut distance = BTreeMap::from([(root, 0)]);
+        let mut parents: BTreeMap<&String, Vec<&String>> = BTreeMap::new();
+        let mut frontier = vec![root];
+        let mut depth = 0;
+        while !frontier.is_empty() && !parents.contains_key(&dependency.to_string()) {
+            depth += 1;
+            let mut next = vec![];
+            for current in frontier {
+                for (dep, _) in self.followed(current, current == root) {
+                    match distance.get(dep) {
+                        None => {
+                            distance.insert(dep, depth);
+                            parents.entry(dep).or_default().push(current);
+                            next.push(dep);
+                        }
+                        Some(d) if *d == depth => parents.entry(dep).or_default().push(current),
+                        Some(_) => {}
+                    }
+                }
+            }
+            frontier = next;
+        }
+
+        let mut res = vec![];
+        if let Some((target, _)) = parents.get_key_value(&dependency.to_string()) {
+            self.collect_chains(&parents, target, &mut vec![], &mut res);
+        }
+        res
+    }
+
+    fn collect_chains<'a>(
+        &'a self,
+        parents: &BTreeMap<&'a String, Vec<&'a String>>,
+        current: &'a String,
+        suffix: &mut Vec<Link<'a>>,
+        res: &mut Vec<Vec<Link<'a>>>,
+    ) {
+        let Some(sources) = parents.get(current) else {
+            res.push(suffix.iter().rev().cloned().collect());
+            return;
+        };
+        for source in sources {
+            suffix.push(Link {
+                package: source,
+                dependency: current,
+                categories: &self.edges[*source][current],
+            });
+            self.collect_chains(parents, source, suffix, res);
+            suffix.pop();
+        }
+    }
+
+    /// Only keep the given packages and the edges between them
+    pub fn restrict(self, packages: &BTreeSet<String>) -> DependencyGraph {
+        let edges = self
+            .edges
+            .into_iter()
+            .filter(|(name, _)| packages.contains(name))
+            .map(|(name, deps)| {
+                let deps = deps
+                    .into_iter()
+                    .filter(|(dep, _)| packages.contains(dep))
+                    .collect();
+                (name, deps)
+            })
+            .collect();
+        DependencyGraph { edges }
+    }
+
+    pub fn render(&self, format: &GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::Mermaid => self.to_mermaid(),
+        }
+    }
+
+    fn to_dot(&self) -> String {
+        let mut res = String::from("digraph dependencies {\n");
+        for (name, deps) in &self.edges {
+            let _ = writeln!(res, "  \"{name}\";");
+            for (dep, categories) in deps {
+                let style = if categories.contains(&Category::Test) && categories.len() == 1 {
+                    ", style=dashed"
+                } else {
+                    ""
+                };
+                let _ = writeln!(
+                    res,
+                    "  \"{name}\" -> \"{dep}\" [label=\"{}\"{style}];",
+                    category_label(categories)
+                );
+            }
+        }
+        res.push_str("}\n");
+        res
+    }
+
+    fn to_mermaid(&self) -> String {
+        let mut res = String::from("graph LR\n");
+        for (name, deps) in &self.edges {
+            let _ = writeln!(res, "  {name}");
+            for (dep, categories) in deps {
+                let arrow = if categories.contains(&Category::Test) && categories.len() == 1 {
+                    "-.->"
+                } else {
+                    "-->"
+                };
+                let _ = writeln!(
+                    res,
+                    "  {name} {arrow}|{}| {dep}",
+                    category_label(categories)
+                );
+            }
+        }
+        res
+    }
+}
+
+fn category_label(categories: &BTreeSet<Category>) -> String {
+    categories
+        .iter()
+        .map(|c| c.name())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{package::Package, workspace::WorkspacePackage};
+
+    /// A workspace of packages with the given dependency tags
+    fn workspace(packages: &[(&str, &str)]) -> Workspace {
+        let packages = packages
+            .iter()
+            .map(|(name, deps)| {
+                let xml = format!("<package format=\"3\"><name>{name}</name>{deps}</package>");
+                let package = WorkspacePackage {
+                    path: PathBuf::from("src").join(name),
+                    manifest: Package::parse(&xml).unwrap(),
+                };
+                (name.to_string(), package)
+            })
+            .collect();
+        Workspace {
+            root: PathBuf::from("/ws"),
+            packages,
+        }
+    }
+
+    fn example() -> DependencyGraph {
+        DependencyGraph::new(&workspace(&[
+            (
+                "app",
+                "<depend>rclcpp</depend><build_depend>lib</build_depend>\
+                 <test_depend>fixtures</test_depend>",
+            ),
+            (
+                "lib",
+                "<build_depend>codegen</build_depend><exec_depend>runtime</exec_depend>",
+            ),
+            ("runtime", "<test_depend>fixtures</test_depend>"),
+            ("codegen", ""),
+            ("fixtures", ""),
+            ("unrelated", "<depend>app</depend>"),
+        ]))
+    }
+
+    fn names(packages: &[&str]) -> BTreeSet<String> {
+        packages.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn ignores_packages_outside_the_workspace() {
+        let graph = example();
+        assert!(graph.contains("app"));
+        assert!(!graph.contains("rclcpp"));
+        assert!(!graph.edges["app"].contains_key("rclcpp"));
+    }
+
+    #[test]
+    fn follows_only_runtime_dependencies_below_the_root() {
+        let graph = example();
+        assert_eq!(
+            graph.up_to("app"),
+            names(&["app", "fixtures", "lib", "runtime"])
+        );
+        assert_eq!(graph.up_to("lib"), names(&["codegen", "lib", "runtime"]));
+        assert_eq!(graph.up_to("codegen"), names(&["codegen"]));
+    }
+
+    #[test]
+    fn renders_test_dependencies_dashed() {
+        let graph = example().restrict(&names(&["app", "fixtures", "lib"]));
+        assert_eq!(
+            graph.render(&GraphFormat::Dot),
+            "digraph dependencies {\n\
+             \x20 \"app\";\n\
+             \x20 \"app\" -> \"fixtures\" [label=\"test\", style=dashed];\n\
+             \x20 \"app\" -> \"lib\" [label=\"build\"];\n\
+             \x20 \"fixtures\";\n\
+             \x20 \"lib\";\n\
+             }\n"
+        );
+        assert_eq!(
+            graph.render(&GraphFormat::Mermaid),
+            "graph LR\n  app\n  app -.->|test| fixtures\n  app -->|build| lib\n  fixtures\n  lib\n"
+        );
+    }
+
+    #[test]
+    fn finds_dependents_like_packages_above() {
+        let graph = example();
+        // `unrelated` only pulls in the runtime dependencies of `app`
+        assert_eq!(graph.dependents("lib"), names(&["app"]));
+        assert_eq!(graph.dependents("fixtures"), names(&["app", "runtime"]));
+        assert_eq!(graph.dependents("codegen"), names(&["lib"]));
+        assert!(graph.dependents("unrelated").is_empty());
+    }
+
+    fn chains(graph: &DependencyGraph, package: &str, dependency: &str) -> Vec<String> {
+        graph
+            .shortest_chains(package, dependency)
+            .iter()
+            .map(|c| format_chain(c))
+            .collect()
+    }
+
+    #[test]
+    fn finds_chains_colcon_follows() {
+        let graph = example();
+        assert_eq!(
+            chains(&graph, "app", "runtime"),
+            ["app -(build)-> lib -(exec)-> runtime"]
+        );
+        assert_eq!(
+            chains(&graph, "app", "fixtures"),
+            ["app -(test)-> fixtures"]
+        );
+        // A build dependency of a dependency isn't part of the up-to build
+        assert!(chains(&graph, "app", "codegen").is_empty());
+        assert!(chains(&graph, "unknown", "lib").is_empty());
+    }
+
+    #[test]
+    fn finds_all_shortest_chains() {
+        let graph = DependencyGraph::new(&workspace(&[
+            ("top", "<depend>left</depend><depend>right</depend>"),
+            ("left", "<exec_depend>base</exec_depend>"),
+            (
+                "right",
+                "<exec_depend>mid</exec_depend><exec_depend>base</exec_depend>",
+            ),
+            ("mid", "<exec_depend>base</exec_depend>"),
+            ("base", ""),
+        ]));
+        assert_eq!(
+            chains(&graph, "top", "base"),
+            [
+                "top -(build, exec)-> left -(exec)-> base",
+                "top -(build, exec)-> right -(exec)-> base"
+            ]
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use toml::{Table, Value};
+
+use crate::migration;
+
+/// A configuration file, together with how to refer to it in messages
+pub struct ConfigFile {
+    pub path: PathBuf,
+    pub display: String,
+}
+
+/// The user-global configuration file (`$XDG_CONFIG_HOME/colb/config.toml`)
+pub fn user_config_file() -> Option<ConfigFile> {
+    let (base, display_base) = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => {
+            let dir = PathBuf::from(dir);
+            let display = dir.to_string_lossy().to_string();
+            (dir, display)
+        }
+        _ => (
+            PathBuf::from(env::var_os("HOME")?).join(".config"),
+            "~/.config".to_string(),
+        ),
+    };
+    Some(ConfigFile {
+        path: base.join("colb").join("config.toml"),
+        display: format!("{display_base}/colb/config.toml"),
+    })
+}
+
+/// The merged contents of several configuration files
+pub struct Layers {
+    pub value: Value,
+    /// Which layer set each value, by dotted key
+    pub origins: BTreeMap<String, String>,
+}
+
+impl Layers {
+    /// Start from the given defaults
+    pub fn new(defaults: Value) -> Layers {
+        let mut origins = BTreeMap::new();
+        record_origins(&defaults, "", "default", &mut origins);
+        Layers {
+            value: defaults,
+            origins,
+        }
+    }
+
+    /// Merge a file on top, so that its values take precedence
+    pub fn add_file(&mut self, file: &ConfigFile) -> Result<(), String> {
+        let data = fs::read_to_string(&file.path)
+            .map_err(|e| format!("Could not open config file '{}': {e}", file.display))?;
+        let mut table = data
+            .parse::<Table>()
+            .map_err(|e| format!("Could not parse config file '{}': {e}", file.display))?;
+        migration::migrate(&mut table, &file.display);
+        self.add(table, &file.display);
+        Ok(())
+    }
+
+    fn add(&mut self, table: Table, origin: &str) {
+        let Value::Table(base) = &mut self.value else {
+            unreachable!("the defaults are always a table");
+        };
+        merge(base, table, "", origin, &mut self.origins);
+    }
+
+    /// Copy all values from the table at `from` which are missing in the table at `to`
+    pub fn inherit(&mut self, from: &str, to: &str) {
+        let Some(Value::Table(source)) = self.get(from) else {
+            return;
+        };
+        let source = source.clone();
+        let Some(target) = self.table_mut(to) else {
+            return;
+        };
+        let mut added = vec![];
+        fill_missing(target, &source, "", &mut added);
+        for key in added {
+            if let Some(origin) = self.origins.get(&join_key(from, &key)).cloned() {
+                self.origins.insert(join_key(to, &key), origin);
+            }
+        }
+    }
+
+    /// The value at a dotted key
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        key.split('.')
+            .try_fold(&self.value, |v, k| v.as_table()?.get(k))
+    }
+
+    /// The table at `key`, which is created if it doesn't exist yet
+    fn table_mut(&mut self, key: &str) -> Option<&mut Table> {
+        key.split('.').try_fold(self.value.as_table_mut()?, |t, k| {
+            t.entry(k)
+                .or_insert_with(|| Value::Table(Table::new()))
+                .as_table_mut()
+        })
+    }
+
+    /// All values as `(dotted key, value, origin)`, sorted by key
+    pub fn entries(&self) -> Vec<(String, &Value, &str)> {
+        let mut res = vec![];
+        flatten(&self.value, "", &mut res);
+        res.into_iter()
+            .map(|(key, value)| {
+                let origin = self
+                    .origins
+                    .get(&key)
+                    .map(|o| o.as_str())
+                    .unwrap_or("default");
+                (key, value, origin)
+            })
+            .collect()
+    }
+}
+
+fn join_key(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{prefix}.{key}")
+    }
+}
+
+/// Tables are merged recursively, everything else (including lists) is replaced
+fn merge(
+    base: &mut Table,
+    layer: Table,
+    prefix: &str,
+    origin: &str,
+    origins: &mut BTreeMap<String, String>,
+) {
+    for (key, value) in layer {
+        let full_key = join_key(prefix, &key);
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(existing)), Value::Table(table)) => {
+                merge(existing, table, &full_key, origin, origins);
+            }
+            (_, value) => {
+                origins.retain(|k, _| k != &full_key && !k.starts_with(&format!("{full_key}.")));
+                record_origins(&value, &full_key, origin, origins);
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Recursively add the values of `source` missing in `target`, collecting the added leaf keys
+fn fill_missing(target: &mut Table, source: &Table, prefix: &str, added: &mut Vec<String>) {
+    for (key, value) in source {
+        let full_key = join_key(prefix, key);
+        match (target.get_mut(key), value) {
+            (Some(Value::Table(existing)), Value::Table(table)) => {
+                fill_missing(existing, table, &full_key, added);
+            }
+            (Some(_), _) => {}
+            (None, value) => {
+                let mut leaves = BTreeMap::new();
+                record_origins(value, &full_key, "", &mut leaves);
+                added.extend(leaves.into_keys());
+                target.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+fn record_origins(value: &Value, key: &str, origin: &str, origins: &mut BTreeMap<String, String>) {
+    match value {
+        Value::Table(table) => {
+            for (k, v) in table {
+                record_origins(v, &join_key(key, k), origin, origins);
+            }
+        }
+        _ => {
+            origins.insert(key.to_string(), origin.to_string());
+        }
+    }
+}
+
+fn flatten<'a>(value: &'a Value, key: &str, res: &mut Vec<(String, &'a Value)>) {
+    match value {
+        Value::Table(table) => {
+            for (k, v) in table {
+                flatten(v, &join_key(key, k), res);
+            }
+        }
+        _ => res.push((key.to_string(), value)),
+    }
+}
+
+/// The configuration files of a workspace that exist, from lowest to highest precedence
+pub fn config_files(workspace: &Path, workspace_file: &str, local_file: &str) -> Vec<ConfigFile> {
+    let workspace_files = [workspace_file, local_file].map(|name| ConfigFile {
+        path: workspace.join(name),
+        display: name.to_string(),
+    });
+    user_config_file()
+        .into_iter()
+        .chain(workspace_files)
+        .filter(|f| f.path.is_file())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(data: &str) -> Table {
+        data.parse().unwrap()
+    }
+
+    fn defaults() -> Layers {
+        Layers::new(Value::Table(table(
+            "[package]\nparallel_jobs = 8\nmixins = [\"ccache\", \"ninja\"]\n\
+             [package.event_handlers]\nsummary = false\n",
+        )))
+    }
+
+    fn origin<'a>(layers: &'a Layers, key: &str) -> &'a str {
+        layers
+            .entries()
+            .into_iter()
+            .find(|(k, _, _)| k == key)
+            .map(|(_, _, origin)| origin)
+            .unwrap()
+    }
+
+    #[test]
+    fn merges_tables_and_replaces_values() {
+        let mut layers = defaults();
+        layers.add(table("[package]\nparallel_jobs = 2\n"), "user");
+        layers.add(table("[package]\nmixins = [\"mold\"]\n"), "local");
+        assert_eq!(
+            layers.value["package"]["parallel_jobs"].as_integer(),
+            Some(2)
+        );
+        // Lists are replaced as a whole
+        assert_eq!(
+            layers.value["package"]["mixins"],
+            Value::Array(vec!["mold".into()])
+        );
+        assert_eq!(origin(&layers, "package.parallel_jobs"), "user");
+        assert_eq!(origin(&layers, "package.mixins"), "local");
+        assert_eq!(origin(&layers, "package.event_handlers.summary"), "default");
+    }
+
+    #[test]
+    fn replaces_origins_of_overwritten_tables() {
+        let mut layers = defaults();
+        layers.add(table("[package]\nevent_handlers = 1\n"), "broken");
+        let keys = layers
+            .entries()
+            .into_iter()
+            .map(|(k, _, origin)| format!("{k} {origin}"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            [
+                "package.event_handlers broken",
+                "package.mixins default",
+                "package.parallel_jobs default"
+            ]
+        );
+    }
+
+    #[test]
+    fn inherits_missing_values() {
+        let mut layers = defaults();
+        layers.add(
+            table("[profiles.ci.package]\nparallel_jobs = 2\n"),
+            "workspace",
+        );
+        layers.inherit("package", "profiles.ci.package");
+        layers.inherit("upstream", "profiles.ci.upstream");
+        let profile = &layers.value["profiles"]["ci"]["package"];
+        assert_eq!(profile["parallel_jobs"].as_integer(), Some(2));
+        assert_eq!(profile["mixins"], layers.value["package"]["mixins"]);
+        assert_eq!(
+            origin(&layers, "profiles.ci.package.parallel_jobs"),
+            "workspace"
+        );
+        assert_eq!(origin(&layers, "profiles.ci.package.mixins"), "default");
+        assert_eq!(
+            origin(&layers, "profiles.ci.package.event_handlers.summary"),
+            "default"
+        );
+    }
+}
@@ -1,31 +1,237 @@
+mod complete;
+mod ctest;
+mod edit;
+mod fuzzy;
+mod graph;
+mod layers;
+mod migration;
+mod overrides;
+mod package;
+mod schema;
+mod script;
+mod state;
+mod watch;
+mod workspace;
+
 use anstyle::{AnsiColor, Color, Style};
+use package::{Package, MANIFEST_FILENAME};
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     env, fs,
     io::{IsTerminal, Write},
     ops::Deref,
     path::{Path, PathBuf},
-    process::{Command, ExitStatus},
+    process::{Command, ExitStatus, Stdio},
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
 };
 
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::{ArgValueCandidates, CompleteEnv, CompletionCandidate};
+use graph::{format_chain, DependencyGraph, GraphFormat};
+use layers::{ConfigFile, Layers};
+use overrides::Assignment;
+use state::{BuildState, Fingerprint};
+use workspace::Workspace;
 
-#[derive(Serialize, Deserialize, clap::ValueEnum, Default, Clone)]
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq)]
+#[serde(try_from = "String", into = "String")]
 enum BuildType {
     #[default]
     Debug,
     Release,
     RelWithDebInfo,
+    MinSizeRel,
+    /// AddressSanitizer
+    Asan,
+    /// UndefinedBehaviorSanitizer
+    Ubsan,
+    /// ThreadSanitizer
+    Tsan,
+    /// MemorySanitizer, needs clang
+    Msan,
+    /// Any other CMAKE_BUILD_TYPE the packages know about, e.g. `Coverage`
+    Custom(String),
 }
 
 impl BuildType {
-    fn apply(&self, cmd: &mut ArgStack) {
-        let t = match self {
+    const NAMED: [BuildType; 8] = [
+        BuildType::Debug,
+        BuildType::Release,
+        BuildType::RelWithDebInfo,
+        BuildType::MinSizeRel,
+        BuildType::Asan,
+        BuildType::Ubsan,
+        BuildType::Tsan,
+        BuildType::Msan,
+    ];
+
+    fn name(&self) -> &str {
+        match self {
             BuildType::Debug => "Debug",
             BuildType::Release => "Release",
             BuildType::RelWithDebInfo => "RelWithDebInfo",
+            BuildType::MinSizeRel => "MinSizeRel",
+            BuildType::Asan => "Asan",
+            BuildType::Ubsan => "Ubsan",
+            BuildType::Tsan => "Tsan",
+            BuildType::Msan => "Msan",
+            BuildType::Custom(name) => name,
+        }
+    }
+
+    /// The compiler and linker flags of the sanitizer builds
+    fn sanitizer_flags(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            BuildType::Asan => Some((
+                "-fsanitize=address -fno-omit-frame-pointer",
+                "-fsanitize=address",
+            )),
+            BuildType::Ubsan => Some((
+                "-fsanitize=undefined -fno-omit-frame-pointer",
+                "-fsanitize=undefined",
+            )),
+            BuildType::Tsan => Some(("-fsanitize=thread", "-fsanitize=thread")),
+            BuildType::Msan => Some((
+                "-fsanitize=memory -fsanitize-memory-track-origins -fno-omit-frame-pointer",
+                "-fsanitize=memory",
+            )),
+            _ => None,
+        }
+    }
+
+    /// The `CMAKE_BUILD_TYPE`, sanitizer builds are debug builds with additional flags
+    fn cmake_build_type(&self) -> &str {
+        if self.sanitizer_flags().is_some() {
+            "Debug"
+        } else {
+            self.name()
+        }
+    }
+
+    /// Add the sanitizer flags to the CMake arguments of a build with the given `CMAKE_BUILD_TYPE`
+    ///
+    /// CMake ignores the `_INIT` variables once the cache exists, so the flags are appended to the
+    /// values given earlier in the arguments. Linker mixins like `mold` set the general linker
+    /// flags after these, so the sanitizer goes into the linker flags of the build type instead.
+    fn add_sanitizer_flags(&self, args: &mut Vec<String>, cmake_build_type: &str) {
+        let Some((compile, link)) = self.sanitizer_flags() else {
+            return;
+        };
+        for lang in ["C", "CXX"] {
+            append_flags(args, &format!("CMAKE_{lang}_FLAGS"), compile);
+        }
+        let config = cmake_build_type.to_uppercase();
+        for kind in ["EXE", "SHARED", "MODULE"] {
+            append_flags(args, &format!("CMAKE_{kind}_LINKER_FLAGS_{config}"), link);
+        }
+    }
+
+    fn apply(&self, cmd: &mut ArgStack) {
+        cmd.arg(cmake_arg("CMAKE_BUILD_TYPE", self.cmake_build_type()));
+        self.add_sanitizer_flags(&mut cmd.args, self.cmake_build_type());
+    }
+}
+
+/// Append flags to a CMake variable, merging all earlier `-D<name>=` arguments into one
+fn append_flags(args: &mut Vec<String>, name: &str, flags: &str) {
+    let prefix = format!("-D{name}");
+    let mut values = vec![];
+    args.retain(|arg| {
+        let Some((kind, value)) = arg.strip_prefix(&prefix).and_then(|a| a.split_once('=')) else {
+            return true;
         };
-        cmd.arg(format!("-DCMAKE_BUILD_TYPE={t}"));
+        // Also matches `-D<name>:STRING=`, but not variables which merely start with the name
+        if !kind.is_empty() && !kind.starts_with(':') {
+            return true;
+        }
+        values.push(value.to_string());
+        false
+    });
+    values.push(flags.to_string());
+    args.push(cmake_arg(name, &values.join(" ")));
+}
+
+impl FromStr for BuildType {
+    type Err = String;
+
+    /// Accepts the spelling of the configuration file (`RelWithDebInfo`) and of `--build-type` (`rel-with-deb-info`)
+    ///
+    /// Other names are custom build types.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s.replace('-', "").to_lowercase();
+        if let Some(t) = BuildType::NAMED
+            .iter()
+            .find(|t| t.name().to_lowercase() == normalized)
+        {
+            return Ok(t.clone());
+        }
+        if s.is_empty() || s.contains(|c: char| c.is_whitespace() || c == ';') {
+            return Err(format!("Invalid build type '{s}'"));
+        }
+        Ok(BuildType::Custom(s.to_string()))
+    }
+}
+
+impl TryFrom<String> for BuildType {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<BuildType> for String {
+    fn from(value: BuildType) -> Self {
+        value.name().to_string()
+    }
+}
+
+/// The build and install base of colcon, relative to the workspace
+#[derive(Clone)]
+struct Bases {
+    build: String,
+    install: String,
+}
+
+impl Default for Bases {
+    fn default() -> Self {
+        Self {
+            build: "build".into(),
+            install: "install".into(),
+        }
+    }
+}
+
+impl Bases {
+    /// Every build type but Debug gets its own folders, so that switching doesn't throw away the other builds
+    ///
+    /// Dependencies are built into the folders of the package, whatever their own build type is.
+    fn for_build_type_name(name: &str) -> Bases {
+        if name == "debug" {
+            return Bases::default();
+        }
+        Bases {
+            build: format!("build-{name}"),
+            install: format!("install-{name}"),
+        }
+    }
+
+    fn apply(&self, args: &mut ArgStack) {
+        args.args(["--build-base", &self.build, "--install-base", &self.install]);
+    }
+
+    /// The build folder of a package
+    fn build_dir(&self, workspace: &Path, package: &str) -> PathBuf {
+        workspace.join(&self.build).join(package)
+    }
+
+    /// The install folder of a package, unless the install space is merged
+    fn install_dir(&self, workspace: &Path, package: &str) -> PathBuf {
+        workspace.join(&self.install).join(package)
     }
 }
 
@@ -68,6 +274,7 @@ struct ColconInvocation {
 struct BuildVerb {
     args: ArgStack,
     workspace: String,
+    bases: Bases,
 }
 
 struct BasicVerb {
@@ -78,15 +285,20 @@ struct BasicVerb {
 struct ConfiguredBuild {
     args: ArgStack,
     workspace: String,
+    bases: Bases,
+    /// The contents of the meta file with the package overrides
+    overrides: String,
 }
 
 #[derive(Default)]
 struct BuildOutput {
     symlink: bool,
     merge: bool,
+    bases: Bases,
 }
 
 #[derive(Serialize, Deserialize)]
+#[serde(default)]
 struct EventHandlers {
     desktop_notification: bool,
     console_cohesion: bool,
@@ -138,35 +350,244 @@ struct BuildConfiguration {
     mixins: Vec<String>,
     cmake_args: Vec<String>,
     build_type: BuildType,
+    /// `0` leaves the number of workers to colcon
+    #[serde(
+        default,
+        serialize_with = "serialize_jobs",
+        deserialize_with = "deserialize_jobs"
+    )]
     parallel_jobs: Option<u32>,
     event_handlers: EventHandlers,
     build_tests: bool,
 }
 
+/// TOML has no null, so colcon's default number of workers is written as `0`
+fn serialize_jobs<S: serde::Serializer>(
+    jobs: &Option<u32>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u32(jobs.unwrap_or(0))
+}
+
+fn deserialize_jobs<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u32>, D::Error> {
+    Ok(Option::<u32>::deserialize(deserializer)?.filter(|n| *n > 0))
+}
+
+/// Settings from `[packages.<name>]`, which take precedence for that package
+///
+/// The `cmake_args` are appended to the general ones.
+/// `mixins` and `parallel_jobs` only take effect when the package is built on its own,
+/// since colcon can't vary them between the packages of one invocation.
+#[derive(Serialize, Deserialize, Default)]
+struct PackageOverride {
+    mixins: Option<Vec<String>>,
+    cmake_args: Option<Vec<String>>,
+    build_type: Option<BuildType>,
+    parallel_jobs: Option<u32>,
+    build_tests: Option<bool>,
+}
+
+impl PackageOverride {
+    fn apply(&self, config: &mut BuildConfiguration) {
+        if let Some(mixins) = &self.mixins {
+            config.mixins = mixins.clone();
+        }
+        if let Some(cmake_args) = &self.cmake_args {
+            config.cmake_args.extend(cmake_args.iter().cloned());
+        }
+        if let Some(build_type) = &self.build_type {
+            config.build_type = build_type.clone();
+        }
+        if let Some(n) = self.parallel_jobs {
+            config.parallel_jobs = (n > 0).then_some(n);
+        }
+        if let Some(build_tests) = self.build_tests {
+            config.build_tests = build_tests;
+        }
+    }
+
+    /// The CMake arguments to add for this package when it is built together with others
+    fn cmake_args(&self) -> Vec<String> {
+        let mut args = ArgStack::default();
+        args.args(self.cmake_args.iter().flatten().cloned());
+        if let Some(build_tests) = self.build_tests {
+            args.arg(cmake_arg(
+                "BUILD_TESTING",
+                if build_tests { "ON" } else { "OFF" },
+            ));
+        }
+        if let Some(build_type) = &self.build_type {
+            build_type.apply(&mut args);
+        }
+        args.args
+    }
+}
+
 struct TestConfiguration {
     package: String,
-    /// If set, run only this test (using ctest-args)
-    test: Option<String>,
+    /// Also test all packages depending on the package
+    dependents: bool,
+    /// If not empty, run only these tests (using ctest-args)
+    tests: Vec<String>,
     event_handlers: EventHandlers,
+    bases: Bases,
 }
 
 struct TestResultConfig {
     package: String,
+    bases: Bases,
     verbose: bool,
     all: bool,
 }
 
+/// Templates for the build and install base, e.g. `build-{build_type}`
+///
+/// Unset templates fall back to `Bases::for_build_type_name`.
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+struct BaseTemplates {
+    build: Option<String>,
+    install: Option<String>,
+}
+
+/// The placeholders available in `BaseTemplates`
+const BASE_PLACEHOLDERS: &[&str] = &["build_type", "profile"];
+
+impl BaseTemplates {
+    fn is_empty(&self) -> bool {
+        self.build.is_none() && self.install.is_none()
+    }
+
+    /// The folders for the build type, `profile` is the one selected with `--profile`
+    fn resolve(&self, build_type: &BuildType, profile: Option<&str>) -> Result<Bases, String> {
+        let build_type = build_type.name().to_lowercase();
+        let values = [
+            ("build_type", build_type.as_str()),
+            ("profile", profile.unwrap_or("default")),
+        ];
+        let expand = |template: &Option<String>, default: String| match template {
+            Some(t) => expand_template(t, &values),
+            None => Ok(default),
+        };
+        let default = Bases::for_build_type_name(&build_type);
+        Ok(Bases {
+            build: expand(&self.build, default.build)?,
+            install: expand(&self.install, default.install)?,
+        })
+    }
+}
+
+/// Replace the `{name}` placeholders in the template
+fn expand_template(template: &str, values: &[(&str, &str)]) -> Result<String, String> {
+    let mut res = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        res.push_str(&rest[..start]);
+        let Some(len) = rest[start..].find('}') else {
+            return Err(format!("Unclosed '{{' in '{template}'"));
+        };
+        let name = &rest[start + 1..start + len];
+        match values.iter().find(|(k, _)| *k == name) {
+            Some((_, value)) => res.push_str(value),
+            None => {
+                let names = values.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+                return Err(format!(
+                    "Unknown placeholder '{{{name}}}' in '{template}', expected one of: {}",
+                    names.join(", ")
+                ));
+            }
+        }
+        rest = &rest[start + len + 1..];
+    }
+    res.push_str(rest);
+    if res.is_empty() {
+        return Err("Empty folder name".into());
+    }
+    Ok(res)
+}
+
+/// A named alternative to the top level configuration, selected with `--profile`
+#[derive(Serialize, Deserialize)]
+struct Profile {
+    upstream: BuildConfiguration,
+    package: BuildConfiguration,
+    #[serde(default, skip_serializing_if = "BaseTemplates::is_empty")]
+    bases: BaseTemplates,
+}
+
 #[derive(Serialize, Deserialize)]
 struct Config {
+    /// Version of the file format, see `migration`
+    version: u32,
     upstream: BuildConfiguration,
     package: BuildConfiguration,
+    #[serde(default, skip_serializing_if = "BaseTemplates::is_empty")]
+    bases: BaseTemplates,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    profiles: BTreeMap<String, Profile>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    packages: BTreeMap<String, PackageOverride>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            version: migration::CONFIG_VERSION,
             upstream: BuildConfiguration::upstream(),
             package: BuildConfiguration::active(),
+            bases: BaseTemplates::default(),
+            profiles: BTreeMap::new(),
+            packages: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Replace the top level configuration with the one from the profile
+    fn use_profile(&mut self, name: &str) -> Result<(), String> {
+        let Some(profile) = self.profiles.remove(name) else {
+            let available = self.profiles.keys().cloned().collect::<Vec<_>>();
+            if available.is_empty() {
+                return Err(format!("Unknown profile '{name}', no profiles configured"));
+            }
+            return Err(format!(
+                "Unknown profile '{name}', available: {}",
+                available.join(", ")
+            ));
+        };
+        self.upstream = profile.upstream;
+        self.package = profile.package;
+        self.bases = profile.bases;
+        Ok(())
+    }
+
+    /// Apply the `[packages.<name>]` settings to the configuration of the active package
+    fn apply_package_override(&mut self, package: &str) {
+        if let Some(o) = self.packages.get(package) {
+            o.apply(&mut self.package);
+        }
+    }
+
+    /// Build the dependencies with the sanitizer of the package, since they share its folders
+    ///
+    /// MSan and TSan report false positives for code which isn't instrumented.
+    fn sanitize_upstream(&mut self) {
+        if self.upstream.build_type.sanitizer_flags().is_none() {
+            let build_type = self.upstream.build_type.cmake_build_type().to_string();
+            self.package
+                .build_type
+                .add_sanitizer_flags(&mut self.upstream.cmake_args, &build_type);
+        }
+    }
+
+    /// Don't build tests at all, not even for packages whose override enables them
+    fn skip_tests(&mut self) {
+        self.upstream.build_tests = false;
+        self.package.build_tests = false;
+        for o in self.packages.values_mut() {
+            o.build_tests = None;
         }
     }
 }
@@ -174,6 +595,7 @@ impl Default for Config {
 enum What {
     DependenciesFor(String),
     ThisPackage(String),
+    DependentsOf(String),
 }
 
 impl ColconInvocation {
@@ -195,10 +617,10 @@ impl ColconInvocation {
         let mut res = BuildVerb {
             args: self.args,
             workspace: self.workspace,
+            bases: base_setup.bases.clone(),
         };
         res.args.arg("build");
-        res.args
-            .args(["--build-base", "build", "--install-base", "install"]);
+        base_setup.bases.apply(&mut res.args);
         if base_setup.symlink {
             res.args.arg("--symlink-install");
         }
@@ -215,14 +637,19 @@ impl ColconInvocation {
         };
         // TODO: log is probably needed here?
         res.args.arg("test");
+        config.bases.apply(&mut res.args);
         res.args.arg("--event-handlers");
         config.event_handlers.apply(&mut res.args);
         res.args.args(["--ctest-args", "--output-on-failure"]);
-        if let Some(test) = &config.test {
+        if !config.tests.is_empty() {
             res.args.arg("-R");
-            res.args.arg(format!("^{test}$"));
+            res.args.arg(ctest::name_regex(&config.tests));
+        }
+        if config.dependents {
+            res.args.args(["--packages-above", &config.package]);
+        } else {
+            res.args.args(["--packages-select", &config.package]);
         }
-        res.args.args(["--packages-select", &config.package]);
         res
     }
 
@@ -233,8 +660,10 @@ impl ColconInvocation {
         };
         // TODO: log is probably needed here?
         res.args.arg("test-result");
-        res.args
-            .args(["--test-result-base", &format!("build/{}", config.package)]);
+        res.args.args([
+            "--test-result-base",
+            &format!("{}/{}", config.bases.build, config.package),
+        ]);
         if config.verbose {
             res.args.arg("--verbose");
         }
@@ -290,6 +719,8 @@ impl BuildVerb {
         let mut res = ConfiguredBuild {
             args: self.args,
             workspace: self.workspace,
+            bases: self.bases,
+            overrides: String::new(),
         };
         if let Some(n) = config.parallel_jobs {
             let n_arg = format!("{}", n);
@@ -322,7 +753,9 @@ fn use_color() -> bool {
 
 macro_rules! header {
     ($($l:tt)*) => {
-        if use_color() {
+        if json_output() {
+            emit_event(serde_json::json!({ "event": "step", "title": format!($($l)*) }));
+        } else if use_color() {
             print!("{DECO}┌[{DECO:#} {HEADER}");
             print!($($l)*);
             println!("{HEADER:#} {DECO}]{DECO:#}");
@@ -335,7 +768,9 @@ macro_rules! header {
 }
 macro_rules! context {
     ($($l:tt)*) => {
-        if use_color() {
+        if json_output() {
+            emit_event(serde_json::json!({ "event": "context", "message": format!($($l)*) }));
+        } else if use_color() {
             print!("{DECO}└>{DECO:#} ");
             println!($($l)*);
         } else {
@@ -345,13 +780,105 @@ macro_rules! context {
     };
 }
 
+/// The program and arguments of the command
+fn command_args(command: &Command) -> Vec<String> {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|a| a.to_string_lossy().to_string())
+        .collect()
+}
+
 fn print_command(command: &Command) {
-    print_command_arrow(&command.get_program().to_string_lossy());
-    for arg in command.get_args() {
-        print!(" {}", arg.to_string_lossy());
+    let args = command_args(command);
+    if json_output() {
+        let cwd = command.get_current_dir().map(|d| d.to_string_lossy());
+        emit_event(serde_json::json!({ "event": "command", "args": args, "cwd": cwd }));
+        return;
     }
+    print_command_arrow(&script::format_command(&args));
     println!();
-    divider();
+    if !dry_run() {
+        divider();
+    }
+}
+
+/// Set once from `--dry-run` and `--emit-script`
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// Whether commands should only be printed instead of run
+fn dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// Set once from `--output`
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/// Whether to print JSON events instead of the human readable output
+fn json_output() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// Print one line of `--output json`
+fn emit_event(event: serde_json::Value) {
+    println!("{event}");
+}
+
+/// Print and record the command, returns whether it should actually run
+fn announce_command(cmd: &Command) -> bool {
+    print_command(cmd);
+    script::record(
+        cmd.get_current_dir().unwrap_or(Path::new(".")),
+        &command_args(cmd),
+    );
+    !dry_run()
+}
+
+fn report_exit(cmd: &Command, status: ExitStatus, start: Instant) {
+    if json_output() {
+        emit_event(serde_json::json!({
+            "event": "exit",
+            "program": cmd.get_program().to_string_lossy(),
+            "status": status.code(),
+            "duration_ms": start.elapsed().as_millis() as u64,
+        }));
+    }
+}
+
+fn not_found(cmd: &Command) -> String {
+    format!("'{}' not found", cmd.get_program().to_string_lossy())
+}
+
+/// Print the command and run it, a dry run pretends that it succeeded
+fn run_command(cmd: &mut Command) -> ExitStatus {
+    if !announce_command(cmd) {
+        return ExitStatus::default();
+    }
+    if json_output() {
+        // Keep stdout for the events
+        cmd.stdout(Stdio::from(std::io::stderr()));
+    }
+    let start = Instant::now();
+    let status = cmd
+        .status()
+        .unwrap_or_else(|_| panic!("{}", not_found(cmd)));
+    report_exit(cmd, status, start);
+    status
+}
+
+/// Like `run_command`, but returns the output instead of printing it
+fn run_command_captured(cmd: &mut Command) -> (ExitStatus, String) {
+    if !announce_command(cmd) {
+        return (ExitStatus::default(), String::new());
+    }
+    let start = Instant::now();
+    let output = cmd
+        .output()
+        .unwrap_or_else(|_| panic!("{}", not_found(cmd)));
+    report_exit(cmd, output.status, start);
+    (
+        output.status,
+        String::from_utf8_lossy(&output.stdout).to_string(),
+    )
 }
 
 fn print_command_arrow(command: &str) {
@@ -370,7 +897,48 @@ fn divider() {
     }
 }
 
+const OVERRIDES_META_FILENAME: &str = "colb_overrides.meta";
+/// Written to the build folder of a package, see `ConfiguredBuild::is_configured`
+const CONFIGURATION_STAMP_FILENAME: &str = "colb_configuration";
+
 impl ConfiguredBuild {
+    /// Pass the per package CMake arguments to colcon through a meta file
+    fn package_overrides(mut self, overrides: &BTreeMap<String, PackageOverride>) -> Self {
+        if overrides.is_empty() {
+            return self;
+        }
+        let names = overrides
+            .iter()
+            .map(|(name, o)| {
+                (
+                    name.clone(),
+                    serde_json::json!({ "cmake-args": o.cmake_args() }),
+                )
+            })
+            .collect::<serde_json::Map<_, _>>();
+        let meta = serde_json::json!({ "names": names });
+        self.overrides = meta.to_string();
+        let build_base = Path::new(&self.workspace).join(&self.bases.build);
+        let meta_path = build_base.join(OVERRIDES_META_FILENAME);
+        script::record_file(&meta_path, &meta.to_string());
+        let res = if dry_run() {
+            Ok(())
+        } else {
+            fs::create_dir_all(&build_base).and_then(|_| fs::write(&meta_path, meta.to_string()))
+        };
+        match res {
+            // Keep the default, so that a colcon.meta in the workspace is still used
+            Ok(_) => self
+                .args
+                .args(["--metas", "./", &meta_path.to_string_lossy()]),
+            Err(e) => eprintln!(
+                "Warning: Could not write '{}', ignoring package overrides: {e}",
+                meta_path.to_string_lossy()
+            ),
+        }
+        self
+    }
+
     fn run(&self, what: &What) -> ExitStatus {
         let mut cmd = Command::new("colcon");
         cmd.current_dir(&self.workspace);
@@ -383,9 +951,139 @@ impl ConfiguredBuild {
             What::ThisPackage(package) => {
                 cmd.arg("--packages-select").arg(package);
             }
+            What::DependentsOf(package) => {
+                cmd.arg("--packages-above").arg(package);
+                cmd.arg("--packages-skip").arg(package);
+            }
+        }
+        let What::ThisPackage(package) = what else {
+            return run_command(&mut cmd);
+        };
+        // A failed build might leave a half updated configuration behind
+        let stamp = self.configuration_stamp(package);
+        if !dry_run() && stamp.exists() {
+            let _ = fs::remove_file(&stamp);
+        }
+        let status = run_command(&mut cmd);
+        if status.success() && !dry_run() {
+            let _ = fs::write(&stamp, self.args.join("\n"));
+        }
+        status
+    }
+
+    /// Build the dependencies of the package, unless none of them changed since they were last built
+    fn run_dependencies(&self, workspace: &Workspace, package: &str) -> ExitStatus {
+        let what = What::DependenciesFor(package.to_string());
+        // The machine running the script doesn't have the state of this one
+        if script::is_recording() {
+            return self.run(&what);
+        }
+        let Some(fingerprints) = self.upstream_fingerprints(workspace, package) else {
+            return self.run(&what);
+        };
+        if fingerprints.is_empty() {
+            context!("'{package}' has no dependencies in the workspace, nothing to build");
+            return ExitStatus::default();
+        }
+        let state_path = Path::new(&self.workspace)
+            .join(&self.bases.build)
+            .join(state::STATE_FILENAME);
+        let mut state = BuildState::load(&state_path);
+        if state.is_up_to_date(&fingerprints) {
+            context!("Dependencies unchanged since the last build, skipping");
+            return ExitStatus::default();
+        }
+        if dry_run() {
+            return self.run(&what);
+        }
+        let save = |state: &BuildState| {
+            if let Err(e) = state.save() {
+                eprintln!(
+                    "Warning: Could not write '{}': {e}",
+                    state_path.to_string_lossy()
+                );
+            }
+        };
+        // A failed build might leave some of them half built
+        state.invalidate(fingerprints.keys());
+        save(&state);
+        let status = self.run(&what);
+        if status.success() {
+            state.record(fingerprints);
+            save(&state);
+        }
+        status
+    }
+
+    /// Fingerprints of everything `--packages-up-to` builds for the package, if they can be determined
+    fn upstream_fingerprints(
+        &self,
+        workspace: &Workspace,
+        package: &str,
+    ) -> Option<BTreeMap<String, Fingerprint>> {
+        let graph = DependencyGraph::new(workspace);
+        if !graph.contains(package) {
+            return None;
+        }
+        let config = state::config_fingerprint(&[&self.args.join("\n"), &self.overrides]);
+        graph
+            .up_to(package)
+            .into_iter()
+            .filter(|p| p != package)
+            .map(|name| {
+                // The install folder might have been removed in the meantime
+                if !self.bases.install_dir(&workspace.root, &name).is_dir() {
+                    return None;
+                }
+                let path = workspace.root.join(&workspace.get(&name)?.path);
+                let sources = state::source_fingerprint(&path).ok()?;
+                let config = config.clone();
+                Some((name, Fingerprint { sources, config }))
+            })
+            .collect()
+    }
+
+    /// Remembers the arguments colcon last built the package with
+    fn configuration_stamp(&self, package: &str) -> PathBuf {
+        self.bases
+            .build_dir(Path::new(&self.workspace), package)
+            .join(CONFIGURATION_STAMP_FILENAME)
+    }
+
+    /// Whether ninja can rebuild the package, because colcon would configure it just like last time
+    fn is_configured(&self, workspace: &Workspace, package: &str) -> bool {
+        let build_dir = self.bases.build_dir(Path::new(&self.workspace), package);
+        let stamp = self.configuration_stamp(package);
+        let Some(manifest) = workspace
+            .get(package)
+            .map(|p| workspace.root.join(&p.path).join(MANIFEST_FILENAME))
+        else {
+            return false;
+        };
+        let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+        // New dependencies need colcon to update the environment hooks
+        let manifest_changed = match (modified(&manifest), modified(&stamp)) {
+            (Some(manifest), Some(stamp)) => manifest > stamp,
+            _ => true,
+        };
+        build_dir.join("build.ninja").is_file()
+            && !manifest_changed
+            && fs::read_to_string(&stamp).is_ok_and(|s| s == self.args.join("\n"))
+    }
+
+    /// Build and install the package without going through colcon
+    ///
+    /// Changes to the CMake files are still picked up, since ninja reruns CMake for them.
+    fn run_ninja(&self, package: &str) -> ExitStatus {
+        let status = ninja_build_targets(&self.workspace, &self.bases, package, &["all"]);
+        if !status.success() {
+            return status;
         }
-        print_command(&cmd);
-        cmd.status().expect("'colcon' not found")
+        let mut cmd = Command::new("cmake");
+        cmd.current_dir(&self.workspace);
+        cmd.arg("--install");
+        cmd.arg(self.bases.build_dir(Path::new(&self.workspace), package));
+        run_command(&mut cmd)
     }
 }
 
@@ -394,100 +1092,845 @@ impl BasicVerb {
         let mut cmd = Command::new("colcon");
         cmd.current_dir(&self.workspace);
         cmd.args(self.args.iter());
-        print_command(&cmd);
-        cmd.status().expect("'colcon' not found")
+        run_command(&mut cmd)
+    }
+
+    /// Like `run`, but returns the output instead of printing it
+    fn run_captured(&self) -> (ExitStatus, String) {
+        let mut cmd = Command::new("colcon");
+        cmd.current_dir(&self.workspace);
+        cmd.args(self.args.iter());
+        run_command_captured(&mut cmd)
+    }
+}
+
+/// The counts from the `Summary: ...` line of `colcon test-result`
+#[derive(Default, Clone, Copy)]
+struct TestSummary {
+    tests: u32,
+    errors: u32,
+    failures: u32,
+    skipped: u32,
+}
+
+impl TestSummary {
+    fn parse(output: &str) -> Option<TestSummary> {
+        let line = output.lines().find_map(|l| l.strip_prefix("Summary:"))?;
+        let mut res = TestSummary::default();
+        for part in line.split(',') {
+            let mut words = part.split_whitespace();
+            let count = words.next()?.parse::<u32>().ok()?;
+            match words.next()? {
+                w if w.starts_with("test") => res.tests = count,
+                w if w.starts_with("error") => res.errors = count,
+                w if w.starts_with("failure") => res.failures = count,
+                w if w.starts_with("skipped") => res.skipped = count,
+                _ => {}
+            }
+        }
+        Some(res)
     }
+
+    /// The counts from the JUnit file written by `ctest --output-junit`
+    fn from_junit(xml: &str) -> Option<TestSummary> {
+        let doc = roxmltree::Document::parse(xml).ok()?;
+        let suite = doc.root_element();
+        if !suite.has_tag_name("testsuite") {
+            return None;
+        }
+        let count = |name| {
+            suite
+                .attribute(name)
+                .and_then(|v| v.parse::<u32>().ok())
+                .unwrap_or(0)
+        };
+        Some(TestSummary {
+            tests: count("tests"),
+            errors: count("errors"),
+            failures: count("failures"),
+            skipped: count("skipped") + count("disabled"),
+        })
+    }
+
+    fn emit(&self, package: &str) {
+        emit_event(serde_json::json!({
+            "event": "test_summary",
+            "package": package,
+            "tests": self.tests,
+            "errors": self.errors,
+            "failures": self.failures,
+            "skipped": self.skipped,
+        }));
+    }
+
+    fn add(&mut self, other: &TestSummary) {
+        self.tests += other.tests;
+        self.errors += other.errors;
+        self.failures += other.failures;
+        self.skipped += other.skipped;
+    }
+
+    fn row(&self, name: &str) -> [String; 5] {
+        [
+            name.to_string(),
+            self.tests.to_string(),
+            self.errors.to_string(),
+            self.failures.to_string(),
+            self.skipped.to_string(),
+        ]
+    }
+}
+
+/// Collects the test results of several packages into one summary
+///
+/// Details are only printed for packages with errors or failures.
+fn aggregate_test_results(ws: &str, bases: &Bases, packages: &[String]) -> ExitStatus {
+    let mut total = TestSummary::default();
+    let mut rows = vec![];
+    let mut failed = None;
+    for package in packages {
+        let (status, output) = ColconInvocation::new(ws, false)
+            .test_result(&TestResultConfig {
+                package: package.clone(),
+                bases: bases.clone(),
+                verbose: true,
+                all: false,
+            })
+            .run_captured();
+        let summary = TestSummary::parse(&output).unwrap_or_default();
+        if !status.success() {
+            if json_output() {
+                eprint!("{output}");
+            } else {
+                print!("{output}");
+            }
+            failed.get_or_insert(status);
+        }
+        total.add(&summary);
+        rows.push(summary.row(package));
+        if json_output() {
+            summary.emit(package);
+        }
+    }
+    if json_output() {
+        return failed.unwrap_or_default();
+    }
+    header!("Summary");
+    rows.push(total.row("Total"));
+    print_table(
+        &["Package", "Tests", "Errors", "Failures", "Skipped"],
+        &rows,
+    );
+    failed.unwrap_or_default()
 }
 
-fn ninja_build_target(workspace: &str, package: &str, target: &str) -> ExitStatus {
+fn ninja_build_targets(
+    workspace: &str,
+    bases: &Bases,
+    package: &str,
+    targets: &[&str],
+) -> ExitStatus {
     let mut cmd = Command::new("ninja");
+    cmd.current_dir(workspace);
     cmd.arg("-C");
-    cmd.arg(format!("{workspace}/build/{package}"));
-    cmd.arg(target);
-    print_command(&cmd);
-    cmd.status().expect("'ninja' not found")
+    cmd.arg(bases.build_dir(Path::new(workspace), package));
+    cmd.args(targets);
+    run_command(&mut cmd)
+}
+
+/// The executables ninja can build in a build folder, by target name
+fn ninja_executables(build_dir: &Path) -> Vec<String> {
+    let output = Command::new("ninja")
+        .arg("-C")
+        .arg(build_dir)
+        .args(["-t", "targets", "all"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success());
+    let Some(output) = output else {
+        return Vec::new();
+    };
+    let mut targets = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.rsplit_once(": "))
+        .filter(|(_, rule)| rule.contains("EXECUTABLE_LINKER"))
+        .filter_map(|(path, _)| Path::new(path).file_name())
+        .map(|name| name.to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    targets.sort();
+    targets.dedup();
+    targets
 }
 
-fn run_single_ctest(workspace: &str, package: &str, target: &str) -> ExitStatus {
+fn run_ctest(workspace: &str, bases: &Bases, package: &str, tests: &[String]) -> ExitStatus {
     let mut cmd = Command::new("ctest");
+    cmd.current_dir(workspace);
     cmd.arg("--test-dir");
-    cmd.arg(format!("{workspace}/build/{package}"));
+    cmd.arg(bases.build_dir(Path::new(workspace), package));
     cmd.arg("--output-on-failure");
     cmd.arg("-R");
-    cmd.arg(format!("^{target}$"));
-    print_command(&cmd);
-    cmd.status().expect("'ctest' not found")
+    cmd.arg(ctest::name_regex(tests));
+    // Outside of the build folder of the package, so that colcon test-result doesn't count it
+    let junit = Path::new(workspace)
+        .join(&bases.build)
+        .join(format!("colb_ctest_{package}.xml"));
+    if json_output() {
+        cmd.arg("--output-junit");
+        cmd.arg(&junit);
+    }
+    let status = run_command(&mut cmd);
+    if json_output() && !dry_run() {
+        fs::read_to_string(&junit)
+            .ok()
+            .and_then(|xml| TestSummary::from_junit(&xml))
+            .unwrap_or_default()
+            .emit(package);
+    }
+    status
 }
 
-fn clean_package(workspace: &Path, package: &str) {
-    let build_folder = workspace.join("build").join(package);
-    let install_folder = workspace.join("install").join(package);
-    let report_error = |err| {
-        eprintln!("Error: {err}");
-    };
-    let mut did_stuff = false;
-    if build_folder.exists() {
-        print_command_arrow("rm -r ");
-        println!("'{}'", build_folder.to_string_lossy());
-        let _ = fs::remove_dir_all(build_folder).map_err(report_error);
-        did_stuff = true;
+/// Print the tests of a package as CTest knows them, without building anything
+fn list_tests(workspace: &str, bases: &Bases, package: &str) {
+    let build_dir = bases.build_dir(Path::new(workspace), package);
+    if !build_dir.is_dir() && !dry_run() {
+        eprintln!("'{package}' has not been built yet, try 'colb build {package}' first");
+        std::process::exit(-1);
     }
-    if install_folder.exists() {
-        print_command_arrow("rm -r ");
-        println!("'{}'", install_folder.to_string_lossy());
-        let _ = fs::remove_dir_all(install_folder).map_err(report_error);
-        did_stuff = true;
+    let mut cmd = ctest::show_only_command(&build_dir);
+    cmd.current_dir(workspace);
+    cmd.stderr(Stdio::inherit());
+    let (status, output) = run_command_captured(&mut cmd);
+    exit_on_error(status);
+    if dry_run() {
+        return;
     }
-    if !did_stuff {
-        print_command_arrow("# Nothing to clean up\n");
+    let tests = ctest::parse_test_list(&output).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(-1);
+    });
+    // Executables in the workspace are easier to read relative to it
+    let executable = |t: &ctest::TestInfo| {
+        let exe = t.executable.clone().unwrap_or_default();
+        Path::new(&exe)
+            .strip_prefix(workspace)
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or(exe)
+    };
+    if json_output() {
+        for t in &tests {
+            emit_event(serde_json::json!({
+                "event": "test",
+                "package": package,
+                "name": t.name,
+                "labels": t.labels,
+                "executable": t.executable,
+            }));
+        }
+        return;
     }
+    let rows = tests
+        .iter()
+        .map(|t| [t.name.clone(), t.labels.join(", "), executable(t)])
+        .collect::<Vec<_>>();
+    print_table(&["Test", "Labels", "Executable"], &rows);
 }
 
-fn contains_marker(path: &Path, markers: &[&str]) -> bool {
-    for m in markers {
-        let candidate = path.join(m);
-        if let Ok(x) = candidate.try_exists() {
-            if x {
-                return true;
-            }
+fn clean_package(workspace: &Path, bases: &Bases, package: &str) {
+    let build_folder = bases.build_dir(workspace, package);
+    let install_folder = bases.install_dir(workspace, package);
+    let mut did_stuff = false;
+    for folder in [build_folder, install_folder] {
+        if !folder.exists() {
+            continue;
+        }
+        did_stuff = true;
+        // Only printed and recorded, the removal itself doesn't need rm
+        let mut rm = Command::new("rm");
+        rm.current_dir(workspace).arg("-r").arg(&folder);
+        if !announce_command(&rm) {
+            continue;
+        }
+        let start = Instant::now();
+        match fs::remove_dir_all(&folder) {
+            Ok(_) => report_exit(&rm, ExitStatus::default(), start),
+            Err(err) => eprintln!("Error: {err}"),
         }
     }
-    false
+    if !did_stuff {
+        context!("Nothing to clean up");
+    }
 }
 
-/// Search upward, and if we hit a package.xml, use that folder name as the package
-fn find_upwards(markers: &[&str]) -> Option<PathBuf> {
-    let mut cwd = env::current_dir().and_then(|p| p.canonicalize()).ok();
-    while let Some(p) = cwd {
-        if contains_marker(&p, markers) {
-            return Some(p.to_path_buf());
+fn print_table<const N: usize>(titles: &[&str; N], rows: &[[String; N]]) {
+    let widths = titles.map(|t| t.len());
+    let widths = rows.iter().fold(widths, |mut w, row| {
+        for (w, cell) in w.iter_mut().zip(row) {
+            *w = (*w).max(cell.len());
         }
-        cwd = p.parent().map(|x| x.to_path_buf());
+        w
+    });
+    let print_row = |row: &[String]| {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, w)| format!("{cell:w$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    };
+    print_row(&titles.map(String::from));
+    for row in rows {
+        print_row(row);
     }
-    None
 }
 
-fn package_or(package: Option<String>) -> Option<String> {
-    if package.is_some() {
-        return package;
+fn list_packages(workspace: &Workspace, bases: &Bases) {
+    let yes_no = |b: bool| if b { "yes" } else { "no" };
+    let rows = workspace
+        .packages
+        .iter()
+        .map(|(name, p)| {
+            [
+                name.clone(),
+                p.manifest.version.clone(),
+                p.path.to_string_lossy().to_string(),
+                p.manifest.build_type().to_string(),
+                yes_no(workspace.is_built(name, &bases.build)).to_string(),
+                yes_no(workspace.is_installed(name, &bases.install)).to_string(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    if json_output() {
+        for [name, version, path, build_type, built, installed] in rows {
+            emit_event(serde_json::json!({
+                "event": "package",
+                "name": name,
+                "version": version,
+                "path": path,
+                "build_type": build_type,
+                "built": built == "yes",
+                "installed": installed == "yes",
+            }));
+        }
+        return;
     }
-    find_upwards(&["package.xml"])
-        .and_then(|f| f.file_name().map(|n| n.to_string_lossy().to_string()))
+    print_table(
+        &[
+            "Package",
+            "Version",
+            "Path",
+            "Build type",
+            "Built",
+            "Installed",
+        ],
+        &rows,
+    );
 }
 
-const COLB_CONFIG_FILENAME: &str = ".colb.toml";
-
-fn detect_workspace() -> Option<String> {
-    find_upwards(&["build", COLB_CONFIG_FILENAME]).map(|n| n.to_string_lossy().to_string())
-}
+/// How long the sources have to stay untouched before a run starts
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Runs colb with `args` once, and again whenever the package sources change
+fn watch_package(workspace: &Workspace, bases: &Bases, package: &str, args: &[String]) {
+    let src = workspace
+        .root
+        .join(&workspace.get(package).expect("validated").path);
+    let ignored = [&bases.build, &bases.install, "log"].map(|d| workspace.root.join(d));
+    let mut watcher = watch::SourceWatcher::new(&src, ignored.to_vec()).unwrap_or_else(|e| {
+        eprintln!("Could not watch '{}': {e}", src.to_string_lossy());
+        std::process::exit(-1);
+    });
+    let colb = env::current_exe().expect("colb executable should exist");
+    watch::catch_interrupt();
+
+    let mut pending_since = Some(Instant::now() - WATCH_DEBOUNCE);
+    let mut running = None;
+    while !watch::interrupted() {
+        if watcher.changed().unwrap_or(true) {
+            pending_since = Some(Instant::now());
+            if let Some(mut child) = running.take() {
+                header!("Sources changed, cancelling current run");
+                let _ = watch::cancel(&mut child);
+            }
+        }
+        if let Some(child) = &mut running {
+            if let Ok(Some(status)) = child.try_wait() {
+                running = None;
+                if status.success() {
+                    header!("Done, watching '{}'", src.to_string_lossy());
+                } else {
+                    header!("Failed ({status}), watching '{}'", src.to_string_lossy());
+                }
+            }
+        }
+        if running.is_none() && pending_since.is_some_and(|t| t.elapsed() >= WATCH_DEBOUNCE) {
+            pending_since = None;
+            let mut cmd = Command::new(&colb);
+            cmd.args(args);
+            running = watch::spawn_cancellable(&mut cmd)
+                .map_err(|e| eprintln!("Could not start colb: {e}"))
+                .ok();
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    if let Some(mut child) = running {
+        let _ = watch::cancel(&mut child);
+    }
+}
+
+fn print_graph(workspace: &Workspace, package: &Option<String>, format: &GraphFormat) {
+    let mut graph = DependencyGraph::new(workspace);
+    if let Some(package) = package {
+        if !graph.contains(package) {
+            eprintln!("Package '{package}' not found in workspace");
+            std::process::exit(-1);
+        }
+        let selection = graph.up_to(package);
+        graph = graph.restrict(&selection);
+    }
+    print!("{}", graph.render(format));
+}
+
+fn explain_dependency(workspace: &Workspace, package: &str, dependency: &str) {
+    let graph = DependencyGraph::new(workspace);
+    for p in [package, dependency] {
+        if !graph.contains(p) {
+            eprintln!("Package '{p}' not found in workspace");
+            std::process::exit(-1);
+        }
+    }
+    let chains = graph.shortest_chains(package, dependency);
+    if chains.is_empty() {
+        if json_output() {
+            context!("'{package}' does not depend on '{dependency}'");
+        } else {
+            println!("'{package}' does not depend on '{dependency}'");
+        }
+        std::process::exit(1);
+    }
+    for chain in chains {
+        if !json_output() {
+            println!("{}", format_chain(&chain));
+            continue;
+        }
+        let links = chain
+            .iter()
+            .map(|l| {
+                let categories = l.categories.iter().map(|c| c.name()).collect::<Vec<_>>();
+                serde_json::json!({
+                    "package": l.package,
+                    "dependency": l.dependency,
+                    "categories": categories,
+                })
+            })
+            .collect::<Vec<_>>();
+        emit_event(serde_json::json!({ "event": "dependency_chain", "links": links }));
+    }
+}
+
+/// The package with this name, or with `--fuzzy` the only one matching it
+fn resolve_package(workspace: &Workspace, package: &str, fuzzy: bool) -> String {
+    if !workspace.has_sources() || workspace.get(package).is_some() {
+        return package.to_string();
+    }
+    let names = workspace
+        .packages
+        .keys()
+        .map(|k| k.as_str())
+        .collect::<Vec<_>>();
+    match fuzzy::resolve(package, &names, fuzzy) {
+        Ok(name) => {
+            context!("Using package '{name}' for '{package}'");
+            name.to_string()
+        }
+        Err(matches) => {
+            eprintln!(
+                "Package '{package}' not found in '{}'{}",
+                workspace.root.join("src").to_string_lossy(),
+                fuzzy::hint(&matches)
+            );
+            std::process::exit(-1);
+        }
+    }
+}
+
+/// The test or ninja target with this name, or with `--fuzzy` the only one matching it
+///
+/// Tests are only known once the package has been configured, until then any name is accepted.
+fn resolve_test(workspace: &str, bases: &Bases, package: &str, test: &str, fuzzy: bool) -> String {
+    let build_dir = bases.build_dir(Path::new(workspace), package);
+    if !build_dir.is_dir() {
+        return test.to_string();
+    }
+    let tests = known_tests(&build_dir);
+    if tests.is_empty() || tests.iter().any(|t| t.name == test) {
+        return test.to_string();
+    }
+    let executables = ninja_executables(&build_dir);
+    if executables.iter().any(|e| e == test) {
+        return test.to_string();
+    }
+    let mut names = tests.iter().map(|t| t.name.as_str()).collect::<Vec<_>>();
+    names.extend(executables.iter().map(String::as_str));
+    names.sort();
+    names.dedup();
+    match fuzzy::resolve(test, &names, fuzzy) {
+        Ok(name) => {
+            context!("Using test '{name}' for '{test}'");
+            name.to_string()
+        }
+        Err(matches) => {
+            eprintln!(
+                "Test '{test}' not found in '{package}'{}",
+                fuzzy::hint(&matches)
+            );
+            std::process::exit(-1);
+        }
+    }
+}
+
+/// The tests CTest knows in a build folder, without printing anything
+fn known_tests(build_dir: &Path) -> Vec<ctest::TestInfo> {
+    ctest::show_only_command(build_dir)
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| ctest::parse_test_list(&String::from_utf8_lossy(&o.stdout)).ok())
+        .unwrap_or_default()
+}
+
+/// The workspace, folders and package of the command line being completed
+fn completion_context() -> Option<(String, Bases, Option<String>)> {
+    let matches = Cli::command()
+        .ignore_errors(true)
+        .try_get_matches_from(complete::completed_words())
+        .ok()?;
+    let ws = matches
+        .get_one::<String>("workspace")
+        .cloned()
+        .or_else(detect_workspace)
+        .unwrap_or(".".into());
+    let (_, verb) = matches.subcommand()?;
+    let profile = verb.get_one::<String>("profile").cloned();
+    let assignments = verb
+        .get_many::<Assignment>("assignments")
+        .map(|a| a.cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+    let files = layers::config_files(
+        Path::new(&ws),
+        COLB_CONFIG_FILENAME,
+        COLB_LOCAL_CONFIG_FILENAME,
+    );
+    let package = verb
+        .try_get_one::<String>("package")
+        .ok()
+        .flatten()
+        .cloned();
+    let package = package_or(package);
+    let mut config = load_layers(&files).ok()?.value.try_into::<Config>().ok()?;
+    apply_cli_overrides(&mut config, &profile, package.as_deref(), &assignments).ok()?;
+    if let Ok(Some(build_type)) = verb.try_get_one::<BuildType>("build_type") {
+        config.package.build_type = build_type.clone();
+    }
+    let bases = config
+        .bases
+        .resolve(&config.package.build_type, profile.as_deref())
+        .ok()?;
+    Some((ws, bases, package))
+}
+
+fn complete_packages() -> Vec<CompletionCandidate> {
+    let ws = completion_context()
+        .map(|(ws, _, _)| ws)
+        .or_else(detect_workspace)
+        .unwrap_or(".".into());
+    Workspace::discover(Path::new(&ws))
+        .packages
+        .into_keys()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// The tests `--test` can build and run, i.e. those with a ninja target of the same name
+///
+/// Without a test list from CTest, all executables are offered.
+fn complete_tests() -> Vec<CompletionCandidate> {
+    let Some((ws, bases, Some(package))) = completion_context() else {
+        return Vec::new();
+    };
+    let build_dir = bases.build_dir(Path::new(&ws), &package);
+    let targets = ninja_executables(&build_dir);
+    let tests = known_tests(&build_dir);
+    if tests.is_empty() {
+        return targets.into_iter().map(CompletionCandidate::new).collect();
+    }
+    tests
+        .into_iter()
+        .filter(|t| targets.is_empty() || targets.contains(&t.name))
+        .map(|t| {
+            let labels = (!t.labels.is_empty()).then(|| t.labels.join(", ").into());
+            CompletionCandidate::new(t.name).help(labels)
+        })
+        .collect()
+}
+
+fn contains_marker(path: &Path, markers: &[&str]) -> bool {
+    for m in markers {
+        let candidate = path.join(m);
+        if let Ok(x) = candidate.try_exists() {
+            if x {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Search upward for the first folder containing one of the markers
+fn find_upwards(markers: &[&str]) -> Option<PathBuf> {
+    find_upwards_where(|p| contains_marker(p, markers))
+}
+
+/// Search upward for the first folder matching the predicate
+fn find_upwards_where(matches: impl Fn(&Path) -> bool) -> Option<PathBuf> {
+    let mut cwd = env::current_dir().and_then(|p| p.canonicalize()).ok();
+    while let Some(p) = cwd {
+        if matches(&p) {
+            return Some(p.to_path_buf());
+        }
+        cwd = p.parent().map(|x| x.to_path_buf());
+    }
+    None
+}
+
+/// Use the given package, or the one declared by the closest package.xml above the current directory
+fn package_or(package: Option<String>) -> Option<String> {
+    if package.is_some() {
+        return package;
+    }
+    let package_dir = find_upwards(&[MANIFEST_FILENAME])?;
+    match Package::from_dir(&package_dir) {
+        Ok(p) => Some(p.name),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            None
+        }
+    }
+}
+
+const COLB_CONFIG_FILENAME: &str = ".colb.toml";
+/// Personal settings on top of the shared workspace configuration, not meant to be committed
+const COLB_LOCAL_CONFIG_FILENAME: &str = ".colb.local.toml";
+
+/// The closest folder above which has a configuration file or was built with colcon
+///
+/// Builds of other build types or with `[bases]` templates don't have a `build` folder,
+/// so the bases colcon created are recognized by their contents.
+fn detect_workspace() -> Option<String> {
+    let markers = ["build", COLB_CONFIG_FILENAME, COLB_LOCAL_CONFIG_FILENAME];
+    find_upwards_where(|p| contains_marker(p, &markers) || workspace::contains_colcon_base(p))
+        .map(|n| n.to_string_lossy().to_string())
+}
+
+/// The file written by `colb init`, with the defaults commented out
+///
+/// Values in the workspace file take precedence over the user-global file, so only
+/// the settings which are uncommented are actually set.
+fn default_config_file() -> String {
+    let defaults =
+        toml::to_string_pretty(&Config::default()).expect("Default config should be serializable");
+    let mut res = format!(
+        "version = {}\n\n# The defaults, uncomment a setting and its section to change it\n",
+        migration::CONFIG_VERSION
+    );
+    for line in defaults.lines().filter(|l| !l.starts_with("version")) {
+        if !line.is_empty() {
+            res.push_str("# ");
+            res.push_str(line);
+        }
+        res.push('\n');
+    }
+    res
+}
+
+/// Merge the configuration files on top of the defaults
+fn load_layers(files: &[ConfigFile]) -> Result<Layers, String> {
+    let defaults =
+        toml::Value::try_from(Config::default()).expect("Default config should be serializable");
+    let mut layers = Layers::new(defaults);
+    for file in files {
+        layers.add_file(file)?;
+    }
+    // Profiles only need to contain what differs from the top level configuration
+    let profiles = match layers.value.get("profiles") {
+        Some(toml::Value::Table(profiles)) => profiles.keys().cloned().collect(),
+        _ => vec![],
+    };
+    for profile in profiles {
+        for part in ["upstream", "package", "bases"] {
+            layers.inherit(part, &format!("profiles.{profile}.{part}"));
+        }
+    }
+    Ok(layers)
+}
+
+/// Apply `--profile`, the `[packages.<name>]` settings of the package and `--set`, in this order
+///
+/// Assignments to `packages.*` change the settings of single packages, so they come before those.
+fn apply_cli_overrides(
+    config: &mut Config,
+    profile: &Option<String>,
+    package: Option<&str>,
+    assignments: &[Assignment],
+) -> Result<(), String> {
+    if let Some(profile) = profile {
+        config.use_profile(profile)?;
+    }
+    let (per_package, general): (Vec<_>, Vec<_>) = assignments
+        .iter()
+        .partition(|a| a.key.split('.').next() == Some("packages"));
+    for assignment in per_package {
+        config.apply_assignment(assignment)?;
+    }
+    if let Some(package) = package {
+        config.apply_package_override(package);
+    }
+    for assignment in general {
+        config.apply_assignment(assignment)?;
+    }
+    Ok(())
+}
+
+fn validate_config(files: &[ConfigFile]) -> bool {
+    let mut valid = true;
+    for file in files {
+        let problems = fs::read_to_string(&file.path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| schema::validate(&data));
+        match problems {
+            Ok(problems) => {
+                for problem in &problems {
+                    println!("{}:{}: {}", file.display, problem.line, problem.message);
+                }
+                valid &= problems.is_empty();
+            }
+            Err(e) => {
+                println!("{}: {e}", file.display);
+                valid = false;
+            }
+        }
+    }
+    if valid {
+        let config = load_layers(files)
+            .and_then(|layers| layers.value.try_into::<Config>().map_err(|e| e.to_string()));
+        if let Err(e) = config {
+            println!("{e}");
+            valid = false;
+        }
+    }
+    if valid {
+        let names = files.iter().map(|f| f.display.as_str()).collect::<Vec<_>>();
+        if names.is_empty() {
+            println!("No configuration files found");
+        } else {
+            println!("No problems found in {}", names.join(", "));
+        }
+    }
+    valid
+}
+
+fn print_config_value(layers: &Layers, key: &str) -> Result<(), String> {
+    schema::lookup(key)?;
+    match layers.get(key) {
+        Some(toml::Value::String(s)) => println!("{s}"),
+        Some(toml::Value::Table(t)) => print!(
+            "{}",
+            toml::to_string_pretty(t).expect("Config should be serializable")
+        ),
+        Some(value) => println!("{value}"),
+        None => return Err(format!("'{key}' is not set")),
+    }
+    Ok(())
+}
+
+fn set_config_value(file: &ConfigFile, key: &str, value: &str) -> Result<(), String> {
+    let value = schema::lookup(key)?
+        .parse(value)
+        .map_err(|e| format!("Can't set '{key}': {e}"))?;
+    let data = match fs::read_to_string(&file.path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(format!(
+                "Could not open config file '{}': {e}",
+                file.display
+            ))
+        }
+    };
+    let data = edit::set_value(&data, key, value)
+        .map_err(|e| format!("Could not update config file '{}': {e}", file.display))?;
+    // Show the updated file instead of writing it
+    if dry_run() {
+        print!("{data}");
+        return Ok(());
+    }
+    if let Some(dir) = file.path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Could not create '{}': {e}", dir.to_string_lossy()))?;
+    }
+    fs::write(&file.path, data)
+        .map_err(|e| format!("Could not write config file '{}': {e}", file.display))
+}
+
+fn print_config_origins(layers: &Layers) {
+    for (key, value, origin) in layers.entries() {
+        println!("{key} = {value}  # {origin}");
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    #[default]
+    Human,
+    Json,
+}
 
 /// A colcon wrapper for faster change compile test cycles
 #[derive(Parser)]
 #[command(version, about)]
 struct Cli {
-    #[arg(short, long)]
+    #[arg(short, long, value_hint = clap::ValueHint::DirPath)]
     workspace: Option<String>,
 
+    /// Use a profile from the configuration file instead of the top level configuration
+    #[arg(short, long, global = true)]
+    profile: Option<String>,
+
+    /// Override a configuration value, e.g. `upstream.parallel_jobs=4` or `package.cmake_args+=-DFOO=ON`
+    ///
+    /// Lists are replaced by comma separated values with `=`, `+=` and `-=` add or remove single values.
+    /// Can be given multiple times.
+    #[arg(long = "set", value_name = "KEY=VALUE", global = true)]
+    assignments: Vec<Assignment>,
+
+    /// Print the colcon, ninja, ctest and rm commands in order instead of running them
+    #[arg(long, global = true, default_value_t = false)]
+    dry_run: bool,
+
+    /// Write the commands to a POSIX shell script instead of running them
+    #[arg(long, value_name = "FILE", global = true, value_hint = clap::ValueHint::FilePath)]
+    emit_script: Option<PathBuf>,
+
+    /// Use the closest match for unknown package and test names, if there is only one
+    #[arg(long, global = true, default_value_t = false)]
+    fuzzy: bool,
+
+    /// Print newline delimited JSON events instead of the human readable output
+    ///
+    /// The output of the commands themselves goes to stderr.
+    #[arg(long, value_enum, global = true, default_value_t)]
+    output: OutputFormat,
+
     #[command(subcommand)]
     verb: Verbs,
 }
@@ -503,6 +1946,7 @@ enum Verbs {
     /// Build a package
     Build {
         /// The package to build (default: current directory)
+        #[arg(add = ArgValueCandidates::new(complete_packages))]
         package: Option<String>,
 
         /// Whether to skip rebuilding dependencies
@@ -514,20 +1958,28 @@ enum Verbs {
         skip_tests: bool,
 
         /// Overwrite the build type from the config file
+        ///
+        /// One of Debug, Release, RelWithDebInfo, MinSizeRel, the sanitizer builds Asan, Ubsan, Tsan and Msan,
+        /// or a custom CMAKE_BUILD_TYPE. Build types other than Debug use their own build and install folders.
         #[arg(short, long)]
         build_type: Option<BuildType>,
+
+        /// Also rebuild all packages depending on the package (using the upstream configuration)
+        #[arg(short = 'D', long, default_value_t = false)]
+        dependents: bool,
     },
 
     /// Run tests for a package
     Test {
         /// The package to test (default: current directory)
+        #[arg(add = ArgValueCandidates::new(complete_packages))]
         package: Option<String>,
 
         /// Build and run only this test (default: run all tests)
-        #[arg(short, long)]
+        #[arg(short, long, add = ArgValueCandidates::new(complete_tests))]
         test: Option<String>,
 
-        /// Run through ctest directly (only works for single tests, default: use colcon test)
+        /// Run through ctest directly (only works with --test or --failed, default: use colcon test)
         #[arg(short, long, default_value_t = false)]
         direct: bool,
 
@@ -538,16 +1990,125 @@ enum Verbs {
         /// Rebuild dependencies of package
         #[arg(short, long, default_value_t = false)]
         rebuild_dependencies: bool,
+
+        /// Also rebuild and test all packages depending on the package
+        #[arg(short = 'D', long, default_value_t = false, conflicts_with = "test")]
+        dependents: bool,
+
+        /// List the tests of the package with their labels and executables instead of running them
+        #[arg(
+            short,
+            long,
+            default_value_t = false,
+            conflicts_with_all = ["test", "direct", "dependents", "rebuild_dependencies"]
+        )]
+        list: bool,
+
+        /// Test the build of this build type instead of the configured one
+        #[arg(short, long)]
+        build_type: Option<BuildType>,
+
+        /// Rebuild and run only the tests that failed in the last run of the package
+        #[arg(
+            short,
+            long,
+            default_value_t = false,
+            conflicts_with_all = ["test", "dependents", "list"]
+        )]
+        failed: bool,
     },
     /// Remove build and install folders of a package
     ///
     /// Note: Does not support merged install spaces
     Clean {
         /// The package to clean
+        #[arg(add = ArgValueCandidates::new(complete_packages))]
+        package: String,
+
+        /// Clean the folders of this build type instead of the configured one
+        #[arg(short, long)]
+        build_type: Option<BuildType>,
+    },
+    /// Rebuild a package whenever its sources change
+    ///
+    /// A run that is still in progress is cancelled when new changes arrive.
+    Watch {
+        /// The package to watch (default: current directory)
+        #[arg(add = ArgValueCandidates::new(complete_packages))]
+        package: Option<String>,
+
+        /// Build and run only this test after each change
+        #[arg(short, long, add = ArgValueCandidates::new(complete_tests))]
+        test: Option<String>,
+
+        /// Run all tests of the package after each change
+        #[arg(short, long, default_value_t = false, conflicts_with = "test")]
+        all_tests: bool,
+    },
+    /// List the packages in the workspace
+    List {},
+    /// Print the dependency graph of the workspace
+    Graph {
+        /// Only show this package and the dependencies built for it
+        #[arg(add = ArgValueCandidates::new(complete_packages))]
+        package: Option<String>,
+
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t)]
+        format: GraphFormat,
+    },
+    /// Explain why a package depends on another one
+    ///
+    /// Prints every shortest dependency chain along which building the dependencies
+    /// of <PACKAGE> pulls in <DEPENDENCY>.
+    Why {
+        /// The package whose dependencies are built
+        #[arg(add = ArgValueCandidates::new(complete_packages))]
         package: String,
+
+        /// The (transitive) dependency in question
+        #[arg(add = ArgValueCandidates::new(complete_packages))]
+        dependency: String,
     },
     /// Opens the configuration file in $EDITOR
-    Config {},
+    ///
+    /// Settings are merged from the user configuration (~/.config/colb/config.toml),
+    /// the workspace configuration (.colb.toml) and personal workspace settings (.colb.local.toml).
+    Config {
+        #[command(subcommand)]
+        action: Option<ConfigAction>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print every configuration value together with the file it comes from
+    Show {
+        /// Print the configuration actually used as TOML, with profile and overrides applied
+        #[arg(short, long, default_value_t = false)]
+        effective: bool,
+    },
+    /// Check the configuration files for unknown keys and invalid values
+    Validate {},
+    /// Print the value of a configuration key, e.g. `package.build_type`
+    Get { key: String },
+    /// Change a value in the workspace configuration file, keeping its comments and formatting
+    ///
+    /// Values use the same syntax as `--set`, lists are given as comma separated values.
+    Set {
+        key: String,
+
+        #[arg(allow_hyphen_values = true)]
+        value: String,
+
+        /// Change the personal workspace settings (.colb.local.toml) instead
+        #[arg(short, long, default_value_t = false, conflicts_with = "user")]
+        local: bool,
+
+        /// Change the user configuration (~/.config/colb/config.toml) instead
+        #[arg(short, long, default_value_t = false)]
+        user: bool,
+    },
 }
 
 fn exit_on_error(status: ExitStatus) {
@@ -583,9 +2144,6 @@ fn colb_config(cfg_file_path: &PathBuf) {
     }
 }
 
-// TODOs:
-// - Allow updating options via command line (f.e. `colb build foo --build-type Release`)
-
 fn main() {
     let exit_on_not_found = || {
         eprintln!("Could not detect package, try specifying it explicitly!");
@@ -593,16 +2151,20 @@ fn main() {
     };
 
     let config_file_err = |err| {
-        eprintln!("Could not open config file: {}", err);
+        eprintln!("{}", err);
         std::process::exit(-1);
     };
 
     let config_parse_err = |err| {
         eprintln!("Could not parse config file: {}", err);
+        eprintln!("Run 'colb config validate' for details");
         std::process::exit(-1);
     };
 
+    CompleteEnv::with_factory(Cli::command).complete();
     let cli = Cli::parse();
+    DRY_RUN.store(cli.dry_run || cli.emit_script.is_some(), Ordering::Relaxed);
+    JSON_OUTPUT.store(cli.output == OutputFormat::Json, Ordering::Relaxed);
     let ws = cli
         .workspace
         .or_else(detect_workspace)
@@ -612,45 +2174,204 @@ fn main() {
         .map(|x| x.to_string_lossy().to_string())
         .unwrap_or(ws.clone());
     let cfg_file_path = Path::new(&ws).join(COLB_CONFIG_FILENAME);
-    if matches!(cli.verb, Verbs::Config {}) {
-        colb_config(&cfg_file_path);
-        return;
+    let cfg_files = layers::config_files(
+        Path::new(&ws),
+        COLB_CONFIG_FILENAME,
+        COLB_LOCAL_CONFIG_FILENAME,
+    );
+    // Both have to work while a configuration file can't be loaded
+    match &cli.verb {
+        Verbs::Config { action: None } => {
+            colb_config(&cfg_file_path);
+            return;
+        }
+        Verbs::Config {
+            action: Some(ConfigAction::Validate {}),
+        } => {
+            let valid = validate_config(&cfg_files);
+            std::process::exit(if valid { 0 } else { 1 });
+        }
+        _ => {}
+    }
+    let cfg_layers = load_layers(&cfg_files).map_err(config_file_err).unwrap();
+    match &cli.verb {
+        Verbs::Config {
+            action: Some(ConfigAction::Show { effective: false }),
+        } => {
+            print_config_origins(&cfg_layers);
+            return;
+        }
+        Verbs::Config {
+            action: Some(ConfigAction::Show { effective: true }),
+        } => {
+            let mut config = cfg_layers
+                .value
+                .try_into::<Config>()
+                .map_err(config_parse_err)
+                .unwrap();
+            if let Err(e) = apply_cli_overrides(&mut config, &cli.profile, None, &cli.assignments) {
+                eprintln!("{e}");
+                std::process::exit(-1);
+            }
+            // Only the selected profile is in effect
+            config.profiles.clear();
+            print!(
+                "{}",
+                toml::to_string_pretty(&config).expect("Config should be serializable")
+            );
+            return;
+        }
+        Verbs::Config {
+            action: Some(ConfigAction::Get { key }),
+        } => {
+            if let Err(e) = print_config_value(&cfg_layers, key) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Verbs::Config {
+            action:
+                Some(ConfigAction::Set {
+                    key,
+                    value,
+                    local,
+                    user,
+                }),
+        } => {
+            let file = if *user {
+                layers::user_config_file().unwrap_or_else(|| {
+                    eprintln!("Could not locate the user configuration, $HOME is not set");
+                    std::process::exit(-1);
+                })
+            } else {
+                let name = if *local {
+                    COLB_LOCAL_CONFIG_FILENAME
+                } else {
+                    COLB_CONFIG_FILENAME
+                };
+                ConfigFile {
+                    path: Path::new(&ws).join(name),
+                    display: name.to_string(),
+                }
+            };
+            if let Err(e) = set_config_value(&file, key, value) {
+                eprintln!("{e}");
+                std::process::exit(-1);
+            }
+            return;
+        }
+        // Keep stdout clean, so that the output can be piped into other tools
+        Verbs::Graph { package, format } => {
+            print_graph(&Workspace::discover(Path::new(&ws_str)), package, format);
+            return;
+        }
+        _ => {}
     }
     header!("Workspace");
-    let mut config = if cfg_file_path.exists() {
+    let mut config = if !cfg_files.is_empty() {
+        let names = cfg_files
+            .iter()
+            .map(|f| f.display.as_str())
+            .collect::<Vec<_>>();
         context!(
             "{} (Using configuration from {})",
             &ws_str,
-            COLB_CONFIG_FILENAME
+            names.join(", ")
         );
-        let data = std::fs::read_to_string(&cfg_file_path)
-            .map_err(config_file_err)
-            .unwrap();
-        toml::from_str::<Config>(&data)
+        cfg_layers
+            .value
+            .try_into::<Config>()
             .map_err(config_parse_err)
             .unwrap()
     } else {
         context!("{} (Unconfigured)", &ws_str);
         Config::default()
     };
+    // The package is needed up front, since its settings come before the command line
+    let active = match &cli.verb {
+        Verbs::Build { package, .. } | Verbs::Test { package, .. } => {
+            let package = package_or(package.clone())
+                .or_else(exit_on_not_found)
+                .expect("should have exited");
+            let workspace = Workspace::discover(Path::new(&ws_str));
+            let package = resolve_package(&workspace, &package, cli.fuzzy);
+            Some((workspace, package))
+        }
+        _ => None,
+    };
+    let override_name = match &cli.verb {
+        Verbs::Clean { package, .. } => Some(package.as_str()),
+        _ => active.as_ref().map(|(_, package)| package.as_str()),
+    };
+    if let Err(e) = apply_cli_overrides(&mut config, &cli.profile, override_name, &cli.assignments)
+    {
+        eprintln!("{e}");
+        std::process::exit(-1);
+    }
+    if let Some(profile) = &cli.profile {
+        context!("Using profile '{profile}'");
+    }
+    for assignment in &cli.assignments {
+        context!("Using {assignment}");
+    }
+    if let Some(path) = &cli.emit_script {
+        if matches!(cli.verb, Verbs::Watch { .. }) {
+            eprintln!("--emit-script can't be combined with watch");
+            std::process::exit(-1);
+        }
+        if let Err(e) = script::start(path, Path::new(&ws_str)) {
+            eprintln!("Could not create '{}': {e}", path.to_string_lossy());
+            std::process::exit(-1);
+        }
+        context!(
+            "Writing the commands to '{}' instead of running them",
+            path.to_string_lossy()
+        );
+    } else if cli.dry_run {
+        context!("Dry run, nothing is executed");
+    }
+    let build_type = match &cli.verb {
+        Verbs::Build { build_type, .. }
+        | Verbs::Test { build_type, .. }
+        | Verbs::Clean { build_type, .. } => build_type.clone(),
+        _ => None,
+    };
+    if let Some(build_type) = build_type {
+        config.package.build_type = build_type;
+    }
+    config.sanitize_upstream();
+    // The folders follow the build type the package ends up with
+    let bases = config
+        .bases
+        .resolve(&config.package.build_type, cli.profile.as_deref())
+        .unwrap_or_else(|e| {
+            eprintln!("Invalid build or install base: {e}");
+            std::process::exit(-1);
+        });
     match &cli.verb {
         Verbs::Init { force } => {
             if cfg_file_path.exists() && !force {
-                println!(
+                eprintln!(
                     "Will not overwrite '{}' without --force",
                     cfg_file_path.to_string_lossy()
                 );
                 std::process::exit(-1);
             }
+            let contents = default_config_file();
+            script::record_file(&cfg_file_path, &contents);
+            if dry_run() {
+                context!(
+                    "Would initialize default configuration at '{}'",
+                    &cfg_file_path.to_string_lossy()
+                );
+                return;
+            }
             match std::fs::File::create(&cfg_file_path) {
                 Ok(mut f) => {
-                    let res = f.write_all(
-                        toml::to_string_pretty(&Config::default())
-                            .expect("Default config should be serializable")
-                            .as_bytes(),
-                    );
+                    let res = f.write_all(contents.as_bytes());
                     if res.is_ok() {
-                        println!(
+                        context!(
                             "Initialized default configuration at '{}'",
                             &cfg_file_path.to_string_lossy()
                         );
@@ -675,81 +2396,171 @@ fn main() {
         }
 
         Verbs::Build {
-            package,
+            package: _,
             skip_dependencies,
             skip_tests,
-            build_type,
+            build_type: _,
+            dependents,
         } => {
+            let (workspace, package) = active.expect("the package is resolved for build");
+            let output = BuildOutput {
+                bases,
+                ..Default::default()
+            };
             if *skip_tests {
-                config.upstream.build_tests = false;
-                config.package.build_tests = false;
+                config.skip_tests();
             }
-            let package = package_or(package.clone())
-                .or_else(exit_on_not_found)
-                .expect("should have exited");
             if !skip_dependencies {
                 header!("Building dependencies for '{}'", package);
                 let status = ColconInvocation::new(&ws, false)
-                    .build(&BuildOutput::default())
+                    .build(&output)
                     .configure(&config.upstream)
-                    .run(&What::DependenciesFor(package.clone()));
+                    .package_overrides(&config.packages)
+                    .run_dependencies(&workspace, &package);
                 exit_on_error(status);
             }
-            if let Some(t) = build_type {
-                config.package.build_type = t.clone();
-            }
             header!("Building '{package}'");
-            let status = ColconInvocation::new(&ws, false)
-                .build(&BuildOutput::default())
-                .configure(&config.package)
-                .run(&What::ThisPackage(package.clone()));
+            let build = ColconInvocation::new(&ws, false)
+                .build(&output)
+                .configure(&config.package);
+            // A script can't rely on the configured build folder of this machine
+            let fast = *skip_dependencies && !script::is_recording();
+            let status = if fast && build.is_configured(&workspace, &package) {
+                context!("Configuration unchanged, building with ninja directly");
+                build.run_ninja(&package)
+            } else {
+                build.run(&What::ThisPackage(package.clone()))
+            };
             exit_on_error(status);
+            if *dependents {
+                header!("Building dependents of '{package}'");
+                let status = ColconInvocation::new(&ws, false)
+                    .build(&output)
+                    .configure(&config.upstream)
+                    .package_overrides(&config.packages)
+                    .run(&What::DependentsOf(package.clone()));
+                exit_on_error(status);
+            }
         }
 
         Verbs::Test {
-            package,
+            package: _,
             test,
             direct,
             skip_rebuild,
             rebuild_dependencies,
+            dependents,
+            list,
+            failed,
+            build_type: _,
         } => {
-            let package = package_or(package.clone())
-                .or_else(exit_on_not_found)
-                .expect("should have exited");
+            let (workspace, package) = active.expect("the package is resolved for test");
+            let test = test
+                .as_ref()
+                .map(|t| resolve_test(&ws, &bases, &package, t, cli.fuzzy));
+            if *list {
+                header!("Tests in '{package}'");
+                list_tests(&ws, &bases, &package);
+                return;
+            }
+            let build_dir = bases.build_dir(Path::new(&ws), &package);
+            let tests = if *failed {
+                ctest::last_failed(&build_dir).unwrap_or_else(|e| {
+                    eprintln!("Could not read the failed tests of '{package}': {e}");
+                    std::process::exit(-1);
+                })
+            } else {
+                test.into_iter().collect::<Vec<_>>()
+            };
+            if *failed && tests.is_empty() {
+                context!("No tests of '{package}' failed in the last run");
+                return;
+            }
+            let what = match tests.as_slice() {
+                [] => None,
+                [test] => Some(format!("test '{test}'")),
+                _ => Some(format!("{} failed tests", tests.len())),
+            };
+            let output = BuildOutput {
+                bases: bases.clone(),
+                ..Default::default()
+            };
             if *rebuild_dependencies && !skip_rebuild {
                 header!("Building dependencies for '{}'", package);
                 let status = ColconInvocation::new(&ws, false)
-                    .build(&BuildOutput::default())
+                    .build(&output)
                     .configure(&config.upstream)
-                    .run(&What::DependenciesFor(package.clone()));
+                    .package_overrides(&config.packages)
+                    .run_dependencies(&workspace, &package);
                 exit_on_error(status);
-                if test.is_some() {
+                if !tests.is_empty() {
                     header!("Building '{package}'");
                     let status = ColconInvocation::new(&ws, false)
-                        .build(&BuildOutput::default())
+                        .build(&output)
                         .configure(&config.package)
                         .run(&What::ThisPackage(package.clone()));
                     exit_on_error(status);
                 }
             }
             if !skip_rebuild {
-                if let Some(test) = test {
-                    header!("Building test '{test}' in '{package}'");
-                    let status = ninja_build_target(&ws, &package, test);
-                    exit_on_error(status);
+                if let Some(what) = &what {
+                    // Linters and other tests without an executable have nothing to build
+                    let executables = if *failed {
+                        ninja_executables(&build_dir)
+                    } else {
+                        tests.clone()
+                    };
+                    let targets = tests
+                        .iter()
+                        .filter(|t| executables.contains(t))
+                        .map(String::as_str)
+                        .collect::<Vec<_>>();
+                    if !targets.is_empty() {
+                        header!("Building {what} in '{package}'");
+                        let status = ninja_build_targets(&ws, &bases, &package, &targets);
+                        exit_on_error(status);
+                    }
                 } else {
                     header!("Building '{package}'");
                     let status = ColconInvocation::new(&ws, false)
-                        .build(&BuildOutput::default())
+                        .build(&output)
                         .configure(&config.package)
                         .run(&What::ThisPackage(package.clone()));
                     exit_on_error(status);
                 }
+                if *dependents {
+                    header!("Building dependents of '{package}'");
+                    config.upstream.build_tests = true;
+                    let status = ColconInvocation::new(&ws, false)
+                        .build(&output)
+                        .configure(&config.upstream)
+                        .package_overrides(&config.packages)
+                        .run(&What::DependentsOf(package.clone()));
+                    exit_on_error(status);
+                }
+            }
+            if *dependents {
+                header!("Running tests for '{package}' and its dependents");
+                let status = ColconInvocation::new(&ws, true)
+                    .test(&TestConfiguration {
+                        package: package.clone(),
+                        dependents: true,
+                        tests: Vec::new(),
+                        event_handlers: EventHandlers::silent(),
+                        bases: bases.clone(),
+                    })
+                    .run();
+                exit_on_error(status);
+                header!("Test results for '{package}' and its dependents");
+                let mut packages = vec![package.clone()];
+                packages.extend(DependencyGraph::new(&workspace).dependents(&package));
+                exit_on_error(aggregate_test_results(&ws, &bases, &packages));
+                return;
             }
-            if let Some(test) = test {
-                header!("Running test '{test}' in '{package}'");
+            if let Some(what) = &what {
+                header!("Running {what} in '{package}'");
                 if *direct {
-                    let status = run_single_ctest(&ws, &package, test);
+                    let status = run_ctest(&ws, &bases, &package, &tests);
                     exit_on_error(status);
                     return;
                 }
@@ -759,15 +2570,24 @@ fn main() {
             let status = ColconInvocation::new(&ws, true)
                 .test(&TestConfiguration {
                     package: package.clone(),
-                    test: test.clone(),
+                    dependents: false,
+                    tests: tests.clone(),
                     event_handlers: EventHandlers::silent(),
+                    bases: bases.clone(),
                 })
                 .run();
             exit_on_error(status);
             header!("Test results for '{package}'");
+            if json_output() {
+                // The summary is only available from the captured output
+                let status = aggregate_test_results(&ws, &bases, std::slice::from_ref(&package));
+                exit_on_error(status);
+                return;
+            }
             let status = ColconInvocation::new(&ws, false)
                 .test_result(&TestResultConfig {
                     package: package.clone(),
+                    bases: bases.clone(),
                     verbose: true,
                     all: true,
                 })
@@ -775,14 +2595,217 @@ fn main() {
             exit_on_error(status);
         }
 
-        Verbs::Clean { package } => {
+        Verbs::Clean {
+            package,
+            build_type: _,
+        } => {
             if package.is_empty() {
                 eprintln!("Package argument must not be empty!",);
             }
+            let built = [
+                bases.build_dir(Path::new(&ws_str), package),
+                bases.install_dir(Path::new(&ws_str), package),
+            ];
+            // Packages which were removed from the sources can still be cleaned up
+            let package = if built.iter().any(|f| f.exists()) {
+                package.clone()
+            } else {
+                resolve_package(&Workspace::discover(Path::new(&ws_str)), package, cli.fuzzy)
+            };
             header!("Cleaning up '{package}'");
-            clean_package(Path::new(&ws_str), package)
+            clean_package(Path::new(&ws_str), &bases, &package)
+        }
+
+        Verbs::Watch {
+            package,
+            test,
+            all_tests,
+        } => {
+            let package = package_or(package.clone())
+                .or_else(exit_on_not_found)
+                .expect("should have exited");
+            let workspace = Workspace::discover(Path::new(&ws_str));
+            if workspace.get(&package).is_none() {
+                eprintln!("Package '{package}' not found in workspace");
+                std::process::exit(-1);
+            }
+            let mut args = vec!["--workspace".to_string(), ws_str.clone()];
+            if let Some(profile) = &cli.profile {
+                args.extend(["--profile".into(), profile.clone()]);
+            }
+            for assignment in &cli.assignments {
+                args.extend(["--set".into(), assignment.to_string()]);
+            }
+            if cli.dry_run {
+                args.push("--dry-run".into());
+            }
+            if cli.output == OutputFormat::Json {
+                args.extend(["--output".into(), "json".into()]);
+            }
+            match test {
+                Some(test) => args.extend([
+                    "test".into(),
+                    package.clone(),
+                    "--test".into(),
+                    test.clone(),
+                ]),
+                None if *all_tests => args.extend(["test".into(), package.clone()]),
+                None => args.extend(["build".into(), "-s".into(), package.clone()]),
+            }
+            header!("Watching '{package}'");
+            watch_package(&workspace, &bases, &package, &args);
+        }
+
+        Verbs::List {} => {
+            header!("Packages");
+            list_packages(&Workspace::discover(Path::new(&ws_str)), &bases);
+        }
+
+        Verbs::Why {
+            package,
+            dependency,
+        } => {
+            header!("Why '{package}' depends on '{dependency}'");
+            explain_dependency(
+                &Workspace::discover(Path::new(&ws_str)),
+                package,
+                dependency,
+            );
         }
 
-        Verbs::Config {} => unreachable!("Handled above"),
+        Verbs::Config { .. } | Verbs::Graph { .. } => unreachable!("Handled above"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_colcon_test_result_summary() {
+        let output = "build/foo/test_results/foo/test_foo.gtest.xml: 3 tests, 0 errors, 1 failure, 0 skipped\n\
+                      Summary: 12 tests, 1 error, 2 failures, 3 skipped\n";
+        let summary = TestSummary::parse(output).unwrap();
+        assert_eq!(
+            (
+                summary.tests,
+                summary.errors,
+                summary.failures,
+                summary.skipped
+            ),
+            (12, 1, 2, 3)
+        );
+    }
+
+    #[test]
+    fn ignores_output_without_summary() {
+        assert!(TestSummary::parse("Starting >>> foo\nFinished <<< foo\n").is_none());
+        assert!(TestSummary::parse("Summary: many tests").is_none());
+    }
+
+    #[test]
+    fn adds_up_summaries() {
+        let mut total = TestSummary::default();
+        total.add(&TestSummary::parse("Summary: 3 tests, 0 errors, 1 failure, 0 skipped").unwrap());
+        total.add(&TestSummary::parse("Summary: 2 tests, 1 error, 0 failures, 1 skipped").unwrap());
+        assert_eq!(
+            (total.tests, total.errors, total.failures, total.skipped),
+            (5, 1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn parses_build_types() {
+        let parse = |s: &str| s.parse::<BuildType>().unwrap();
+        assert!(parse("RelWithDebInfo") == BuildType::RelWithDebInfo);
+        assert!(parse("rel-with-deb-info") == BuildType::RelWithDebInfo);
+        assert!(parse("asan") == BuildType::Asan);
+        assert!(parse("Coverage") == BuildType::Custom("Coverage".into()));
+        assert!(parse("ReleaseLTO") == BuildType::Custom("ReleaseLTO".into()));
+        assert!(parse("Lsan") == BuildType::Custom("Lsan".into()));
+    }
+
+    #[test]
+    fn rejects_invalid_build_types() {
+        assert!("".parse::<BuildType>().is_err());
+        assert!("Release Debug".parse::<BuildType>().is_err());
+        assert!("Debug;-DFOO=ON".parse::<BuildType>().is_err());
+    }
+
+    #[test]
+    fn expands_templates() {
+        let values = [("build_type", "asan"), ("profile", "ci")];
+        assert_eq!(
+            expand_template("build-{profile}-{build_type}", &values).unwrap(),
+            "build-ci-asan"
+        );
+        assert_eq!(expand_template("build", &values).unwrap(), "build");
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        let values = [("build_type", "debug")];
+        assert!(expand_template("build-{build_type", &values).is_err());
+        assert!(expand_template("build-{distro}", &values).is_err());
+        assert!(expand_template("", &values).is_err());
+    }
+
+    #[test]
+    fn resolves_bases_from_templates() {
+        let templates = BaseTemplates {
+            build: Some("build/{build_type}".into()),
+            install: None,
+        };
+        let bases = templates.resolve(&BuildType::Release, None).unwrap();
+        assert_eq!(bases.build, "build/release");
+        assert_eq!(bases.install, "install-release");
+        let bases = BaseTemplates::default()
+            .resolve(&BuildType::Debug, Some("ci"))
+            .unwrap();
+        assert_eq!(
+            (bases.build.as_str(), bases.install.as_str()),
+            ("build", "install")
+        );
+    }
+
+    #[test]
+    fn appends_sanitizer_flags_to_given_flags() {
+        let mut args = [
+            "-DCMAKE_CXX_FLAGS:STRING=-Wall",
+            "-DCMAKE_CXX_FLAGS_DEBUG=-g3",
+        ]
+        .map(String::from)
+        .to_vec();
+        BuildType::Asan.add_sanitizer_flags(&mut args, "Release");
+        assert_eq!(
+            args,
+            [
+                "-DCMAKE_CXX_FLAGS_DEBUG=-g3",
+                "-DCMAKE_C_FLAGS=-fsanitize=address -fno-omit-frame-pointer",
+                "-DCMAKE_CXX_FLAGS=-Wall -fsanitize=address -fno-omit-frame-pointer",
+                "-DCMAKE_EXE_LINKER_FLAGS_RELEASE=-fsanitize=address",
+                "-DCMAKE_SHARED_LINKER_FLAGS_RELEASE=-fsanitize=address",
+                "-DCMAKE_MODULE_LINKER_FLAGS_RELEASE=-fsanitize=address",
+            ]
+        );
+        let mut args = vec![];
+        BuildType::Release.add_sanitizer_flags(&mut args, "Release");
+        assert!(args.is_empty());
+    }
+
+    #[test]
+    fn initializes_configuration_with_commented_defaults() {
+        let contents = default_config_file();
+        let table = contents.parse::<toml::Table>().unwrap();
+        assert_eq!(table.keys().collect::<Vec<_>>(), ["version"]);
+        let uncommented = contents
+            .lines()
+            .map(|l| l.strip_prefix("# ").unwrap_or(l))
+            .filter(|l| !l.starts_with("The defaults"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let config = toml::from_str::<Config>(&uncommented).unwrap();
+        assert_eq!(config.package.parallel_jobs, Some(8));
+        assert!(config.package.build_tests);
     }
 }
@@ -0,0 +1,117 @@
+use toml::{Table, Value};
+
+/// The version of the configuration format written by this colb
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Upgrades a configuration file from the version at the index to the next one
+///
+/// New fields which have a sensible default don't need a migration, since files are always
+/// merged on top of the defaults. Steps are only needed for renamed, moved or reinterpreted values.
+const MIGRATIONS: [fn(&mut Table); CONFIG_VERSION as usize] = [
+    // 0: Files written before the version field was introduced, with the same layout
+    unset_missing_parallel_jobs,
+];
+
+/// A missing `parallel_jobs` used to leave the number of workers to colcon, which is now `0`
+///
+/// Without this, the default of the merged configuration would take its place.
+fn unset_missing_parallel_jobs(table: &mut Table) {
+    let mut configs = vec![];
+    for (key, value) in table.iter_mut() {
+        match (key.as_str(), value) {
+            ("upstream" | "package", Value::Table(config)) => configs.push(config),
+            ("profiles", Value::Table(profiles)) => {
+                for (_, profile) in profiles.iter_mut() {
+                    let Value::Table(profile) = profile else {
+                        continue;
+                    };
+                    for (key, value) in profile.iter_mut() {
+                        if let ("upstream" | "package", Value::Table(config)) =
+                            (key.as_str(), value)
+                        {
+                            configs.push(config);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    for config in configs {
+        config.entry("parallel_jobs").or_insert(Value::Integer(0));
+    }
+}
+
+/// Brings a parsed configuration file up to the current version in place
+///
+/// Files without a `version` are treated as version 0.
+/// The `version` itself is removed, since the result always has the current version.
+pub fn migrate(table: &mut Table, name: &str) {
+    let version = match table.remove("version") {
+        None => 0,
+        Some(Value::Integer(v)) if v >= 0 => v as u32,
+        Some(v) => {
+            eprintln!("Warning: Ignoring invalid version {v} in '{name}'");
+            0
+        }
+    };
+    if version > CONFIG_VERSION {
+        eprintln!(
+            "Warning: '{name}' has version {version}, but this colb only knows up to version {CONFIG_VERSION}. \
+             Unknown settings are ignored, consider upgrading colb."
+        );
+        return;
+    }
+    for step in &MIGRATIONS[version as usize..] {
+        step(table);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn migrated(data: &str) -> Table {
+        let mut table = data.parse::<Table>().unwrap();
+        migrate(&mut table, "test.toml");
+        table
+    }
+
+    #[test]
+    fn removes_the_version() {
+        let table = migrated("version = 1\n[package]\nparallel_jobs = 2\n");
+        assert!(!table.contains_key("version"));
+        assert_eq!(table["package"]["parallel_jobs"].as_integer(), Some(2));
+    }
+
+    #[test]
+    fn migrates_unversioned_files() {
+        let table = migrated(
+            "[upstream]\nbuild_tests = false\n\
+             [package]\nparallel_jobs = 4\n\
+             [profiles.ci.upstream]\nmixins = []\n",
+        );
+        assert_eq!(table["upstream"]["build_tests"].as_bool(), Some(false));
+        // A missing value used to mean colcon's default
+        assert_eq!(table["upstream"]["parallel_jobs"].as_integer(), Some(0));
+        assert_eq!(table["package"]["parallel_jobs"].as_integer(), Some(4));
+        assert_eq!(
+            table["profiles"]["ci"]["upstream"]["parallel_jobs"].as_integer(),
+            Some(0)
+        );
+        let table = migrated("version = 1\n[upstream]\nbuild_tests = false\n");
+        assert!(!table["upstream"]
+            .as_table()
+            .unwrap()
+            .contains_key("parallel_jobs"));
+    }
+
+    #[test]
+    fn keeps_files_from_newer_versions() {
+        let table = migrated("version = 99\n[package]\nnew_setting = true\n");
+        assert!(!table.contains_key("version"));
+        assert_eq!(table["package"]["new_setting"].as_bool(), Some(true));
+        let table = migrated("version = \"one\"\n");
+        assert!(table.is_empty());
+    }
+}
@@ -0,0 +1,225 @@
+use std::{fmt, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::{
+    schema::{self, Schema},
+    Config,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Op {
+    /// `key=value`
+    Set,
+    /// `key+=value`, only for lists
+    Append,
+    /// `key-=value`, only for lists
+    Remove,
+}
+
+/// A `--set` argument like `upstream.parallel_jobs=4` or `package.cmake_args+=-DFOO=ON`
+#[derive(Clone, Debug)]
+pub struct Assignment {
+    pub key: String,
+    pub op: Op,
+    pub value: String,
+}
+
+impl FromStr for Assignment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(eq) = s.find('=') else {
+            return Err(format!(
+                "Expected KEY=VALUE, KEY+=VALUE or KEY-=VALUE, got '{s}'"
+            ));
+        };
+        let (key, op) = match &s[..eq] {
+            k if k.ends_with('+') => (&k[..k.len() - 1], Op::Append),
+            k if k.ends_with('-') => (&k[..k.len() - 1], Op::Remove),
+            k => (k, Op::Set),
+        };
+        if key.is_empty() {
+            return Err(format!("Missing key in '{s}'"));
+        }
+        Ok(Assignment {
+            key: key.trim().to_string(),
+            op,
+            value: s[eq + 1..].to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Assignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self.op {
+            Op::Set => "=",
+            Op::Append => "+=",
+            Op::Remove => "-=",
+        };
+        write!(f, "{}{op}{}", self.key, self.value)
+    }
+}
+
+impl Config {
+    /// Change a value through the configuration as it would be written to a file
+    ///
+    /// The key and the value are checked against the same schema as `config set` and `config validate`.
+    pub fn apply_assignment(&mut self, assignment: &Assignment) -> Result<(), String> {
+        let key = &assignment.key;
+        let schema = schema::lookup(key)?;
+        let mut config = toml::Value::try_from(&*self).expect("Config should be serializable");
+        let path = key.split('.').collect::<Vec<_>>();
+        set(&mut config, &path, schema, assignment.op, &assignment.value)
+            .map_err(|e| format!("Can't set '{key}': {e}"))?;
+        *self = config
+            .try_into()
+            .map_err(|e| format!("Can't set '{key}': {e}"))?;
+        Ok(())
+    }
+}
+
+fn set(
+    config: &mut toml::Value,
+    path: &[&str],
+    schema: &Schema,
+    op: Op,
+    value: &str,
+) -> Result<(), String> {
+    let (name, parents) = path.split_last().expect("Keys have at least one part");
+    // The list to start from with `+=` and `-=` if there is none yet
+    let inherited = match path {
+        // The mixins of a package replace the general ones
+        ["packages", _, "mixins"] => config.get("package").and_then(|p| p.get("mixins")).cloned(),
+        ["packages", _, "cmake_args"] if op == Op::Remove => {
+            return Err(
+                "There are no cmake_args for the package to remove, the general ones always apply"
+                    .into(),
+            );
+        }
+        _ => None,
+    };
+    let mut table = config.as_table_mut().expect("Config is a table");
+    for part in parents {
+        table = table
+            .entry(part.to_string())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| format!("'{part}' is not a table"))?;
+    }
+    if op == Op::Set {
+        // An empty template restores the default folders
+        if matches!(schema, Schema::Template(_)) && value.trim().is_empty() {
+            table.remove(*name);
+            return Ok(());
+        }
+        let value = schema.parse(value)?.to_string();
+        let value = toml::Value::deserialize(toml::de::ValueDeserializer::new(&value))
+            .expect("Parsed values should be valid TOML");
+        table.insert(name.to_string(), value);
+        return Ok(());
+    }
+    if !matches!(schema, Schema::Strings) {
+        return Err("'+=' and '-=' only work for lists".into());
+    }
+    let list = table
+        .entry(name.to_string())
+        .or_insert_with(|| inherited.unwrap_or_else(|| toml::Value::Array(vec![])));
+    let toml::Value::Array(list) = list else {
+        return Err(format!("'{name}' is not a list"));
+    };
+    if op == Op::Append {
+        list.push(value.into());
+    } else {
+        list.retain(|v| v.as_str() != Some(value));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assignment(s: &str) -> Assignment {
+        s.parse().unwrap()
+    }
+
+    fn apply(config: &mut Config, s: &str) -> Result<(), String> {
+        config.apply_assignment(&assignment(s))
+    }
+
+    #[test]
+    fn parses_assignments() {
+        let a = assignment("upstream.parallel_jobs=4");
+        assert_eq!(
+            (a.key.as_str(), a.op, a.value.as_str()),
+            ("upstream.parallel_jobs", Op::Set, "4")
+        );
+        let a = assignment("package.cmake_args+=-DFOO=ON");
+        assert_eq!(
+            (a.key.as_str(), a.op, a.value.as_str()),
+            ("package.cmake_args", Op::Append, "-DFOO=ON")
+        );
+        let a = assignment("package.mixins-=ccache");
+        assert_eq!(
+            (a.key.as_str(), a.op, a.value.as_str()),
+            ("package.mixins", Op::Remove, "ccache")
+        );
+        assert_eq!(assignment("package.cmake_args=").value, "");
+    }
+
+    #[test]
+    fn rejects_malformed_assignments() {
+        assert!("upstream.parallel_jobs".parse::<Assignment>().is_err());
+        assert!("=4".parse::<Assignment>().is_err());
+        assert!("+=4".parse::<Assignment>().is_err());
+    }
+
+    #[test]
+    fn applies_assignments() {
+        let mut config = Config::default();
+        apply(&mut config, "upstream.parallel_jobs=4").unwrap();
+        apply(&mut config, "package.cmake_args=-DA=1, -DB=2").unwrap();
+        apply(&mut config, "package.cmake_args+=-DC=3").unwrap();
+        apply(&mut config, "package.cmake_args-=-DA=1").unwrap();
+        apply(&mut config, "package.event_handlers.summary=on").unwrap();
+        assert_eq!(config.upstream.parallel_jobs, Some(4));
+        assert_eq!(config.package.cmake_args, ["-DB=2", "-DC=3"]);
+        assert!(config.package.event_handlers.summary);
+    }
+
+    #[test]
+    fn rejects_invalid_assignments() {
+        let mut config = Config::default();
+        assert!(apply(&mut config, "upstream.paralel_jobs=4").is_err());
+        assert!(apply(&mut config, "upstream.parallel_jobs=many").is_err());
+        assert!(apply(&mut config, "upstream.parallel_jobs+=4").is_err());
+        assert!(apply(&mut config, "upstream=4").is_err());
+    }
+
+    #[test]
+    fn unsets_counts_like_config_set() {
+        let mut config = Config::default();
+        apply(&mut config, "package.parallel_jobs=4").unwrap();
+        apply(&mut config, "package.parallel_jobs=none").unwrap();
+        assert_eq!(config.package.parallel_jobs, None);
+        assert_eq!(
+            schema::lookup("package.parallel_jobs")
+                .unwrap()
+                .parse("none")
+                .unwrap()
+                .as_integer(),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn removes_mixins_of_a_package_from_the_general_ones() {
+        let mut config = Config::default();
+        apply(&mut config, "packages.foo.mixins-=ccache").unwrap();
+        let mixins = config.packages["foo"].mixins.as_ref().unwrap();
+        assert!(!mixins.is_empty());
+        assert!(!mixins.iter().any(|m| m == "ccache"));
+        assert!(apply(&mut config, "packages.bar.cmake_args-=-DFOO=ON").is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use std::{env, fs, path::Path};
+
+pub const MANIFEST_FILENAME: &str = "package.xml";
+
+/// The dependency tags of a package manifest (REP 140 / REP 149)
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum DependencyKind {
+    Build,
+    BuildExport,
+    Buildtool,
+    BuildtoolExport,
+    Exec,
+    Test,
+    Doc,
+}
+
+impl DependencyKind {
+    /// Maps a manifest tag to the dependency kinds it declares
+    fn from_tag(tag: &str) -> &'static [DependencyKind] {
+        use DependencyKind::*;
+        match tag {
+            "depend" => &[Build, BuildExport, Exec],
+            "build_depend" => &[Build],
+            "build_export_depend" => &[BuildExport],
+            "buildtool_depend" => &[Buildtool],
+            "buildtool_export_depend" => &[BuildtoolExport],
+            "exec_depend" => &[Exec],
+            // Format 1 only, kept so that old manifests don't lose their runtime dependencies
+            "run_depend" => &[BuildExport, Exec],
+            "test_depend" => &[Test],
+            "doc_depend" => &[Doc],
+            _ => &[],
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Dependency {
+    pub name: String,
+    pub kind: DependencyKind,
+}
+
+/// The parts of a `package.xml` (format 2 and 3) colb cares about
+#[derive(Clone, Debug)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    /// Value of `<export><build_type>`, if present
+    pub build_type: Option<String>,
+    /// Dependencies whose format 3 `condition` holds in the current environment
+    pub dependencies: Vec<Dependency>,
+}
+
+impl Package {
+    pub fn from_file(path: &Path) -> Result<Package, String> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read '{}': {e}", path.to_string_lossy()))?;
+        Package::parse(&data).map_err(|e| format!("{}: {e}", path.to_string_lossy()))
+    }
+
+    /// The build type colcon will use, defaulting to `catkin` as specified by REP 149
+    pub fn build_type(&self) -> &str {
+        self.build_type.as_deref().unwrap_or("catkin")
+    }
+
+    pub fn from_dir(dir: &Path) -> Result<Package, String> {
+        Package::from_file(&dir.join(MANIFEST_FILENAME))
+    }
+
+    pub fn parse(data: &str) -> Result<Package, String> {
+        let doc = roxmltree::Document::parse(data).map_err(|e| e.to_string())?;
+        let root = doc.root_element();
+        if root.tag_name().name() != "package" {
+            return Err(format!(
+                "Expected <package> root element, found <{}>",
+                root.tag_name().name()
+            ));
+        }
+        let mut name = None;
+        let mut version = None;
+        let mut build_type = None;
+        let mut dependencies = vec![];
+        for child in root.children().filter(|n| n.is_element()) {
+            let tag = child.tag_name().name();
+            match tag {
+                "name" => name = Some(element_text(&child)),
+                "version" => version = Some(element_text(&child)),
+                "export" => {
+                    build_type = child
+                        .children()
+                        .find(|n| n.has_tag_name("build_type"))
+                        .map(|n| element_text(&n));
+                }
+                _ => {
+                    if !child.attribute("condition").is_none_or(evaluate_condition) {
+                        continue;
+                    }
+                    for kind in DependencyKind::from_tag(tag) {
+                        dependencies.push(Dependency {
+                            name: element_text(&child),
+                            kind: *kind,
+                        });
+                    }
+                }
+            }
+        }
+
+        let name = name
+            .filter(|n| !n.is_empty())
+            .ok_or("Missing <name> element")?;
+        Ok(Package {
+            name,
+            version: version.unwrap_or_default(),
+            build_type,
+            dependencies,
+        })
+    }
+}
+
+fn element_text(node: &roxmltree::Node) -> String {
+    node.text().unwrap_or_default().trim().to_string()
+}
+
+/// Evaluates a REP 149 condition like `$ROS_VERSION == 2 and $ROS_DISTRO != humble`.
+///
+/// Unset variables expand to the empty string, like in catkin_pkg.
+/// Malformed expressions are treated as true, so a dependency is never silently dropped.
+fn evaluate_condition(condition: &str) -> bool {
+    let spaced = condition.replace('(', " ( ").replace(')', " ) ");
+    let tokens: Vec<String> = spaced
+        .split_whitespace()
+        .map(|t| match t.strip_prefix('$') {
+            Some(var) => env::var(var).unwrap_or_default(),
+            None => t.to_string(),
+        })
+        .collect();
+    let mut pos = 0;
+    match parse_or(&tokens, &mut pos) {
+        Some(res) if pos == tokens.len() => res,
+        _ => true,
+    }
+}
+
+fn parse_or(tokens: &[String], pos: &mut usize) -> Option<bool> {
+    let mut res = parse_and(tokens, pos)?;
+    while tokens.get(*pos).is_some_and(|t| t == "or") {
+        *pos += 1;
+        res |= parse_and(tokens, pos)?;
+    }
+    Some(res)
+}
+
+fn parse_and(tokens: &[String], pos: &mut usize) -> Option<bool> {
+    let mut res = parse_comparison(tokens, pos)?;
+    while tokens.get(*pos).is_some_and(|t| t == "and") {
+        *pos += 1;
+        res &= parse_comparison(tokens, pos)?;
+    }
+    Some(res)
+}
+
+fn parse_comparison(tokens: &[String], pos: &mut usize) -> Option<bool> {
+    if tokens.get(*pos)? == "(" {
+        *pos += 1;
+        let res = parse_or(tokens, pos)?;
+        if tokens.get(*pos)? != ")" {
+            return None;
+        }
+        *pos += 1;
+        return Some(res);
+    }
+    let lhs = tokens.get(*pos)?;
+    let op = tokens.get(*pos + 1)?;
+    let rhs = tokens.get(*pos + 2)?;
+    *pos += 3;
+    match op.as_str() {
+        "==" => Some(lhs == rhs),
+        "!=" => Some(lhs != rhs),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_format_3_manifest() {
+        let xml = r#"<?xml version="1.0"?>
+            <package format="3">
+              <name> my_package </name>
+              <version>1.2.3</version>
+              <buildtool_depend>ament_cmake</buildtool_depend>
+              <depend>rclcpp</depend>
+              <test_depend>ament_lint_auto</test_depend>
+              <exec_depend condition="$COLB_UNSET_VARIABLE == 1">ros1_only</exec_depend>
+              <export>
+                <build_type>ament_cmake</build_type>
+              </export>
+            </package>"#;
+        let package = Package::parse(xml).unwrap();
+        assert_eq!(package.name, "my_package");
+        assert_eq!(package.version, "1.2.3");
+        assert_eq!(package.build_type.as_deref(), Some("ament_cmake"));
+        let deps = package
+            .dependencies
+            .iter()
+            .map(|d| (d.name.as_str(), d.kind))
+            .collect::<Vec<_>>();
+        use DependencyKind::*;
+        assert_eq!(
+            deps,
+            [
+                ("ament_cmake", Buildtool),
+                ("rclcpp", Build),
+                ("rclcpp", BuildExport),
+                ("rclcpp", Exec),
+                ("ament_lint_auto", Test),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_format_2_manifest() {
+        let xml = r#"<package format="2">
+              <name>old_package</name>
+              <build_depend>roscpp</build_depend>
+            </package>"#;
+        let package = Package::parse(xml).unwrap();
+        assert_eq!(package.build_type, None);
+        assert_eq!(package.dependencies.len(), 1);
+    }
+
+    #[test]
+    fn rejects_invalid_manifests() {
+        assert!(Package::parse("<package><version>1</version></package>").is_err());
+        assert!(Package::parse("<manifest><name>x</name></manifest>").is_err());
+        assert!(Package::parse("<package>").is_err());
+    }
+
+    #[test]
+    fn evaluates_conditions() {
+        assert!(evaluate_condition("1 == 1"));
+        assert!(!evaluate_condition("1 != 1"));
+        assert!(evaluate_condition("a == b or b == b"));
+        assert!(!evaluate_condition("a == a and a == b"));
+        assert!(evaluate_condition("a == a and ( a == b or c == c )"));
+        assert!(!evaluate_condition("(a == b)"));
+        assert!(!evaluate_condition("$COLB_UNSET_VARIABLE == 2"));
+        assert!(evaluate_condition("$COLB_UNSET_VARIABLE != 2"));
+    }
+
+    #[test]
+    fn treats_malformed_conditions_as_true() {
+        assert!(evaluate_condition("a <= b"));
+        assert!(evaluate_condition("( a == b"));
+        assert!(evaluate_condition("a == b c"));
+    }
+}
@@ -0,0 +1,300 @@
+use toml_edit::{ImDocument, Item, TableLike, Value};
+
+use crate::{expand_template, BuildType, BASE_PLACEHOLDERS};
+
+/// The expected shape of a configuration value
+pub enum Schema {
+    Table(&'static [(&'static str, Schema)]),
+    /// A table with arbitrary keys (e.g. package names), which all have the same schema
+    Map(&'static Schema),
+    Bool,
+    /// A non-negative integer
+    Count,
+    Strings,
+    BuildType,
+    /// A string with `{name}` placeholders
+    Template(&'static [&'static str]),
+}
+
+const EVENT_HANDLERS: Schema = Schema::Table(&[
+    ("desktop_notification", Schema::Bool),
+    ("console_cohesion", Schema::Bool),
+    ("summary", Schema::Bool),
+    ("console_start_end", Schema::Bool),
+]);
+
+const BUILD_CONFIGURATION: Schema = Schema::Table(&[
+    ("mixins", Schema::Strings),
+    ("cmake_args", Schema::Strings),
+    ("build_type", Schema::BuildType),
+    ("parallel_jobs", Schema::Count),
+    ("event_handlers", EVENT_HANDLERS),
+    ("build_tests", Schema::Bool),
+]);
+
+const PACKAGE_OVERRIDE: Schema = Schema::Table(&[
+    ("mixins", Schema::Strings),
+    ("cmake_args", Schema::Strings),
+    ("build_type", Schema::BuildType),
+    ("parallel_jobs", Schema::Count),
+    ("build_tests", Schema::Bool),
+]);
+
+const BASES: Schema = Schema::Table(&[
+    ("build", Schema::Template(BASE_PLACEHOLDERS)),
+    ("install", Schema::Template(BASE_PLACEHOLDERS)),
+]);
+
+const PROFILE: Schema = Schema::Table(&[
+    ("upstream", BUILD_CONFIGURATION),
+    ("package", BUILD_CONFIGURATION),
+    ("bases", BASES),
+]);
+
+const CONFIG: Schema = Schema::Table(&[
+    ("version", Schema::Count),
+    ("upstream", BUILD_CONFIGURATION),
+    ("package", BUILD_CONFIGURATION),
+    ("bases", BASES),
+    ("profiles", Schema::Map(&PROFILE)),
+    ("packages", Schema::Map(&PACKAGE_OVERRIDE)),
+]);
+
+impl Schema {
+    pub fn keys(&self) -> Vec<&'static str> {
+        match self {
+            Schema::Table(fields) => fields.iter().map(|(k, _)| *k).collect(),
+            _ => vec![],
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Schema::Table(_) | Schema::Map(_) => "a table",
+            Schema::Bool => "a boolean",
+            Schema::Count => "a non-negative integer",
+            Schema::Strings => "a list of strings",
+            Schema::BuildType => "a build type",
+            Schema::Template(_) => "a string with known placeholders",
+        }
+    }
+
+    /// Parse a value given on the command line, with the same syntax as `--set`
+    pub fn parse(&self, value: &str) -> Result<Value, String> {
+        let value = value.trim();
+        match self {
+            Schema::Table(_) | Schema::Map(_) => {
+                Err("Expected a key with a value, not a table".into())
+            }
+            Schema::Bool => parse_bool(value).map(Value::from),
+            // `none` unsets counts like `parallel_jobs`, which is written as 0
+            Schema::Count if value.is_empty() || value.eq_ignore_ascii_case("none") => {
+                Ok(Value::from(0))
+            }
+            Schema::Count => value
+                .parse::<u32>()
+                .map(|n| Value::from(n as i64))
+                .map_err(|_| format!("Expected a number or 'none', got '{value}'")),
+            Schema::Strings => Ok(Value::Array(parse_list(value).into_iter().collect())),
+            Schema::BuildType => {
+                let build_type = value.parse::<BuildType>()?;
+                Ok(Value::from(String::from(build_type)))
+            }
+            Schema::Template(placeholders) => {
+                check_template(value, placeholders)?;
+                Ok(Value::from(value))
+            }
+        }
+    }
+
+    fn check(&self, item: &Item) -> bool {
+        match self {
+            Schema::Table(_) | Schema::Map(_) => item.as_table_like().is_some(),
+            Schema::Bool => item.as_bool().is_some(),
+            Schema::Count => item.as_integer().is_some_and(|i| i >= 0),
+            Schema::Strings => item
+                .as_array()
+                .is_some_and(|a| a.iter().all(|v| v.as_str().is_some())),
+            Schema::BuildType => item
+                .as_str()
+                .is_some_and(|s| s.parse::<BuildType>().is_ok()),
+            Schema::Template(placeholders) => item
+                .as_str()
+                .is_some_and(|s| check_template(s, placeholders).is_ok()),
+        }
+    }
+}
+
+fn check_template(template: &str, placeholders: &[&str]) -> Result<String, String> {
+    let values = placeholders.iter().map(|p| (*p, *p)).collect::<Vec<_>>();
+    expand_template(template, &values)
+}
+
+/// The schema of a dotted configuration key like `packages.foo.build_type`
+pub fn lookup(key: &str) -> Result<&'static Schema, String> {
+    let mut schema = &CONFIG;
+    let mut prefix = String::new();
+    for part in key.split('.') {
+        schema = match schema {
+            Schema::Table(fields) => match fields.iter().find(|(k, _)| *k == part) {
+                Some((_, field)) => field,
+                None => {
+                    let full_key = format!("{prefix}{part}");
+                    return Err(match suggest(part, &schema.keys()) {
+                        Some(s) => format!("Unknown key '{full_key}', did you mean '{s}'?"),
+                        None => format!(
+                            "Unknown key '{full_key}', expected one of: {}",
+                            schema.keys().join(", ")
+                        ),
+                    });
+                }
+            },
+            Schema::Map(field) if !part.is_empty() => field,
+            _ => return Err(format!("'{prefix}{part}' is not a table")),
+        };
+        prefix = format!("{prefix}{part}.");
+    }
+    Ok(schema)
+}
+
+pub fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "true" | "on" | "yes" | "1" => Ok(true),
+        "false" | "off" | "no" | "0" => Ok(false),
+        _ => Err(format!("Expected a boolean, got '{value}'")),
+    }
+}
+
+/// Comma separated values, without empty ones
+pub fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+/// The closest match among the candidates, if any is reasonably close
+pub fn suggest<'a>(key: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    candidates
+        .iter()
+        .map(|c| (strsim::jaro_winkler(key, c), *c))
+        .filter(|(score, _)| *score > 0.7)
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, c)| c)
+}
+
+pub struct Problem {
+    pub line: usize,
+    pub message: String,
+}
+
+/// Checks a configuration file for unknown keys and values of the wrong type
+pub fn validate(data: &str) -> Result<Vec<Problem>, String> {
+    let doc = ImDocument::parse(data).map_err(|e| e.to_string())?;
+    let mut problems = vec![];
+    let line_of = |span: Option<std::ops::Range<usize>>| {
+        span.map_or(0, |s| data[..s.start].matches('\n').count() + 1)
+    };
+    check_table(doc.as_table(), &CONFIG, "", &line_of, &mut problems);
+    problems.sort_by_key(|p| p.line);
+    Ok(problems)
+}
+
+fn check_table(
+    table: &dyn TableLike,
+    schema: &Schema,
+    prefix: &str,
+    line_of: &dyn Fn(Option<std::ops::Range<usize>>) -> usize,
+    problems: &mut Vec<Problem>,
+) {
+    for (key, item) in table.iter() {
+        let full_key = if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{prefix}.{key}")
+        };
+        let line = line_of(table.key(key).and_then(|k| k.span()).or(item.span()));
+        let field = match schema {
+            Schema::Table(fields) => fields.iter().find(|(k, _)| *k == key).map(|(_, s)| s),
+            Schema::Map(schema) => Some(*schema),
+            _ => None,
+        };
+        let Some(field) = field else {
+            let message = match suggest(key, &schema.keys()) {
+                Some(s) => format!("Unknown key '{full_key}', did you mean '{s}'?"),
+                None => format!("Unknown key '{full_key}'"),
+            };
+            problems.push(Problem { line, message });
+            continue;
+        };
+        if !field.check(item) {
+            problems.push(Problem {
+                line,
+                message: format!("'{full_key}' must be {}", field.describe()),
+            });
+            continue;
+        }
+        if let Some(t) = item.as_table_like() {
+            check_table(t, field, &full_key, line_of, problems);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(data: &str) -> Vec<(usize, String)> {
+        validate(data)
+            .unwrap()
+            .into_iter()
+            .map(|p| (p.line, p.message))
+            .collect()
+    }
+
+    #[test]
+    fn accepts_valid_files() {
+        let data = "version = 1\n\
+                    [package]\nbuild_type = \"Release\"\nmixins = [\"ccache\"]\n\
+                    [packages.foo]\nparallel_jobs = 2\n\
+                    [profiles.asan.upstream.event_handlers]\nsummary = true\n";
+        assert!(problems(data).is_empty());
+    }
+
+    #[test]
+    fn reports_unknown_keys_with_suggestions() {
+        let data = "[package]\nparalel_jobs = 4\n\n[upstream]\nfrobnicate = true\n";
+        assert_eq!(
+            problems(data),
+            [
+                (
+                    2,
+                    "Unknown key 'package.paralel_jobs', did you mean 'parallel_jobs'?".to_string()
+                ),
+                (5, "Unknown key 'upstream.frobnicate'".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_wrong_types() {
+        let data = "[package]\nparallel_jobs = -1\nmixins = [1]\n[packages]\nfoo = 3\n";
+        assert_eq!(
+            problems(data),
+            [
+                (
+                    2,
+                    "'package.parallel_jobs' must be a non-negative integer".to_string()
+                ),
+                (3, "'package.mixins' must be a list of strings".to_string()),
+                (5, "'packages.foo' must be a table".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_syntax_errors() {
+        assert!(validate("[package\n").is_err());
+    }
+}
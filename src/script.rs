@@ -0,0 +1,227 @@
+use std::{
+    borrow::Cow,
+    fs::{self, File},
+    io::{self, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// The script written by `--emit-script`, if any
+static SCRIPT: Mutex<Option<Script>> = Mutex::new(None);
+
+/// A POSIX shell script that reproduces the commands colb runs
+///
+/// Paths in the workspace are written relative to `$WS`, so the script can be run on a copy of it
+/// by setting `COLB_WORKSPACE`.
+struct Script {
+    out: File,
+    path: PathBuf,
+    /// The workspace the script was generated in
+    workspace: PathBuf,
+    /// The working directory after the commands so far
+    cwd: PathBuf,
+}
+
+impl Script {
+    fn create(path: &Path, workspace: &Path) -> io::Result<Script> {
+        let workspace = workspace
+            .canonicalize()
+            .unwrap_or_else(|_| workspace.to_path_buf());
+        let mut out = File::create(path)?;
+        writeln!(out, "#!/bin/sh")?;
+        writeln!(out, "# Generated by colb")?;
+        writeln!(out, "set -e")?;
+        writeln!(
+            out,
+            "WS=\"${{COLB_WORKSPACE:-{}}}\"",
+            double_quote(&workspace.to_string_lossy())
+        )?;
+        writeln!(out, "cd \"$WS\"")?;
+        out.set_permissions(fs::Permissions::from_mode(0o755))?;
+        Ok(Script {
+            out,
+            path: path.to_path_buf(),
+            cwd: workspace.clone(),
+            workspace,
+        })
+    }
+
+    /// The path relative to the workspace, if it is in there
+    fn relative(&self, path: &Path) -> Option<PathBuf> {
+        let path = std::path::absolute(path).ok()?;
+        // Files which are only created by the script can't be canonicalized
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        [canonical, path]
+            .into_iter()
+            .find_map(|p| Some(p.strip_prefix(&self.workspace).ok()?.to_path_buf()))
+    }
+
+    /// The path as it is written in the script, relative to the workspace if it is in there
+    fn path(&self, path: &Path) -> String {
+        match self.relative(path) {
+            Some(relative) if self.cwd != self.workspace => in_workspace(&relative),
+            Some(relative) if relative.as_os_str().is_empty() => ".".into(),
+            Some(relative) => quote(&relative.to_string_lossy()).into_owned(),
+            None => quote(&path.to_string_lossy()).into_owned(),
+        }
+    }
+
+    /// An argument as it is written in the script, where absolute paths can be relative to `$WS`
+    fn arg(&self, arg: &str) -> String {
+        let path = Path::new(arg);
+        if path.is_absolute() && self.relative(path).is_some() {
+            return self.path(path);
+        }
+        quote(arg).into_owned()
+    }
+
+    fn change_dir(&mut self, dir: &Path) -> io::Result<()> {
+        // Relative paths would depend on where the script is run from
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        if self.cwd != dir {
+            let target = match self.relative(&dir) {
+                Some(relative) => in_workspace(&relative),
+                None => quote(&dir.to_string_lossy()).into_owned(),
+            };
+            writeln!(self.out, "cd {target}")?;
+            self.cwd = dir;
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, cwd: &Path, args: &[String]) -> io::Result<()> {
+        self.change_dir(cwd)?;
+        let args = args.iter().map(|a| self.arg(a)).collect::<Vec<_>>();
+        writeln!(self.out, "{}", args.join(" "))
+    }
+
+    fn write_file(&mut self, path: &Path, contents: &str) -> io::Result<()> {
+        let path = std::path::absolute(path)?;
+        if let Some(dir) = path.parent() {
+            writeln!(self.out, "mkdir -p {}", self.path(dir))?;
+        }
+        writeln!(
+            self.out,
+            "cat > {} <<'COLB_EOF'\n{contents}\nCOLB_EOF",
+            self.path(&path)
+        )
+    }
+}
+
+/// A path relative to the workspace, for any working directory
+fn in_workspace(relative: &Path) -> String {
+    if relative.as_os_str().is_empty() {
+        return "\"$WS\"".into();
+    }
+    format!("\"$WS\"/{}", quote(&relative.to_string_lossy()))
+}
+
+/// Write all commands to a script at `path` from now on, with paths relative to `workspace`
+pub fn start(path: &Path, workspace: &Path) -> io::Result<()> {
+    let script = Script::create(path, workspace)?;
+    *SCRIPT.lock().expect("script lock") = Some(script);
+    Ok(())
+}
+
+/// Whether the commands are written to a script, which has to work without the local build state
+pub fn is_recording() -> bool {
+    SCRIPT.lock().expect("script lock").is_some()
+}
+
+fn with_script(f: impl FnOnce(&mut Script) -> io::Result<()>) {
+    if let Some(script) = SCRIPT.lock().expect("script lock").as_mut() {
+        if let Err(e) = f(script) {
+            eprintln!(
+                "Could not write to '{}': {e}",
+                script.path.to_string_lossy()
+            );
+            std::process::exit(-1);
+        }
+    }
+}
+
+/// Add a command which runs in `cwd` to the script
+pub fn record(cwd: &Path, args: &[String]) {
+    with_script(|s| s.command(cwd, args));
+}
+
+/// Add creating a file with the given contents to the script
+pub fn record_file(path: &Path, contents: &str) {
+    with_script(|s| s.write_file(path, contents));
+}
+
+/// Quote an argument for a POSIX shell, if necessary
+pub fn quote(arg: &str) -> Cow<'_, str> {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        return Cow::Borrowed(arg);
+    }
+    Cow::Owned(format!("'{}'", arg.replace('\'', r"'\''")))
+}
+
+/// Escape a string for use within double quotes
+fn double_quote(arg: &str) -> String {
+    arg.chars().fold(String::new(), |mut s, c| {
+        if "\"\\$`".contains(c) {
+            s.push('\\');
+        }
+        s.push(c);
+        s
+    })
+}
+
+pub fn format_command(args: &[String]) -> String {
+    args.iter().map(|a| quote(a)).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_plain_arguments_alone() {
+        assert_eq!(
+            quote("-DCMAKE_BUILD_TYPE=Debug"),
+            "-DCMAKE_BUILD_TYPE=Debug"
+        );
+        assert_eq!(quote("/ws/build/my_pkg"), "/ws/build/my_pkg");
+    }
+
+    #[test]
+    fn quotes_special_characters() {
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("a b"), "'a b'");
+        assert_eq!(quote("$HOME"), "'$HOME'");
+        assert_eq!(quote("^(a|b)$"), "'^(a|b)$'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn formats_commands() {
+        let args = ["ctest", "-R", "^test node$"].map(String::from);
+        assert_eq!(format_command(&args), "ctest -R '^test node$'");
+    }
+
+    #[test]
+    fn writes_paths_relative_to_the_workspace() {
+        let ws = std::env::temp_dir().join(format!("colb-{}-script", std::process::id()));
+        fs::create_dir_all(ws.join("build/pkg")).unwrap();
+        let path = ws.join("run.sh");
+        let mut script = Script::create(&path, &ws).unwrap();
+        let build_dir = ws.join("build/pkg").to_string_lossy().to_string();
+        script
+            .command(&ws, &["ninja".into(), "-C".into(), build_dir])
+            .unwrap();
+        script.write_file(&ws.join("build/x.meta"), "{}").unwrap();
+        script
+            .command(&ws.join("build/pkg"), &["ctest".into(), "/usr/bin".into()])
+            .unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&ws).unwrap();
+        assert!(text.contains("\ncd \"$WS\"\n"));
+        assert!(text.contains("\nninja -C build/pkg\n"));
+        assert!(text.contains("\ncat > build/x.meta <<"));
+        assert!(text.contains("\ncd \"$WS\"/build/pkg\nctest /usr/bin\n"));
+    }
+}
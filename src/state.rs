@@ -0,0 +1,185 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Name of the state file in the build base
+pub const STATE_FILENAME: &str = "colb_state.json";
+
+/// What an upstream package was last built from
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct Fingerprint {
+    pub sources: String,
+    pub config: String,
+}
+
+/// Remembers which upstream packages are up to date, so that their build can be skipped
+#[derive(Serialize, Deserialize, Default)]
+pub struct BuildState {
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(default)]
+    upstream: BTreeMap<String, Fingerprint>,
+}
+
+impl BuildState {
+    /// Load the state file, a missing or unreadable one is treated as empty
+    pub fn load(path: &Path) -> BuildState {
+        let mut res = fs::read_to_string(path)
+            .ok()
+            .and_then(|data| serde_json::from_str::<BuildState>(&data).ok())
+            .unwrap_or_default();
+        res.path = path.to_path_buf();
+        res
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let data = serde_json::to_string_pretty(self).expect("State should be serializable");
+        fs::write(&self.path, data)
+    }
+
+    /// Whether all packages were last built from exactly these fingerprints
+    pub fn is_up_to_date(&self, fingerprints: &BTreeMap<String, Fingerprint>) -> bool {
+        fingerprints
+            .iter()
+            .all(|(name, f)| self.upstream.get(name) == Some(f))
+    }
+
+    pub fn record(&mut self, fingerprints: BTreeMap<String, Fingerprint>) {
+        self.upstream.extend(fingerprints);
+    }
+
+    /// Forget the packages, e.g. before a build which might fail halfway
+    pub fn invalidate<'a>(&mut self, packages: impl IntoIterator<Item = &'a String>) {
+        for name in packages {
+            self.upstream.remove(name);
+        }
+    }
+}
+
+/// A hash of the paths, sizes and modification times of all files below `dir`
+///
+/// Hidden files and folders (like `.git`) are left out. The hash is only meant for
+/// comparing with earlier runs, so a changing hash function merely causes a rebuild.
+pub fn source_fingerprint(dir: &Path) -> io::Result<String> {
+    let mut hasher = DefaultHasher::new();
+    hash_dir(dir, Path::new(""), &mut hasher)?;
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+fn hash_dir(dir: &Path, relative: &Path, hasher: &mut DefaultHasher) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let name = entry.file_name();
+        if name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = relative.join(&name);
+        let meta = entry.metadata()?;
+        if meta.is_dir() {
+            hash_dir(&entry.path(), &path, hasher)?;
+            continue;
+        }
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        (path, meta.len(), modified).hash(hasher);
+    }
+    Ok(())
+}
+
+/// A hash of the configuration a package is built with
+pub fn config_fingerprint(parts: &[&str]) -> String {
+    let mut hasher = DefaultHasher::new();
+    parts.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    /// An empty folder below the system's temporary folder, unique to the test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("colb-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn fingerprints(entries: &[(&str, &str)]) -> BTreeMap<String, Fingerprint> {
+        entries
+            .iter()
+            .map(|(name, sources)| {
+                let fingerprint = Fingerprint {
+                    sources: sources.to_string(),
+                    config: config_fingerprint(&["Release"]),
+                };
+                (name.to_string(), fingerprint)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compares_with_recorded_fingerprints() {
+        let mut state = BuildState::default();
+        let current = fingerprints(&[("a", "1"), ("b", "2")]);
+        assert!(!state.is_up_to_date(&current));
+        state.record(current.clone());
+        assert!(state.is_up_to_date(&current));
+        assert!(state.is_up_to_date(&fingerprints(&[("a", "1")])));
+        assert!(!state.is_up_to_date(&fingerprints(&[("a", "1"), ("b", "3")])));
+        state.invalidate(&["b".to_string()]);
+        assert!(!state.is_up_to_date(&current));
+    }
+
+    #[test]
+    fn saves_and_loads_state() {
+        let dir = temp_dir("state");
+        let path = dir.join("build").join(STATE_FILENAME);
+        let mut state = BuildState::load(&path);
+        state.record(fingerprints(&[("a", "1")]));
+        state.save().unwrap();
+        assert!(BuildState::load(&path).is_up_to_date(&fingerprints(&[("a", "1")])));
+        fs::write(&path, "not json").unwrap();
+        assert!(!BuildState::load(&path).is_up_to_date(&fingerprints(&[("a", "1")])));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn fingerprints_visible_sources() {
+        let dir = temp_dir("sources");
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("package.xml"), "<package/>").unwrap();
+        fs::write(dir.join("src").join("main.cpp"), "int main() {}").unwrap();
+        let initial = source_fingerprint(&dir).unwrap();
+        assert_eq!(source_fingerprint(&dir).unwrap(), initial);
+
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(dir.join(".git").join("HEAD"), "ref: refs/heads/main").unwrap();
+        assert_eq!(source_fingerprint(&dir).unwrap(), initial);
+
+        fs::write(dir.join("src").join("main.cpp"), "int main() { return 1; }").unwrap();
+        assert_ne!(source_fingerprint(&dir).unwrap(), initial);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn fails_for_missing_sources() {
+        assert!(source_fingerprint(Path::new("/nonexistent/colb/package")).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+
+/// How long a cancelled run gets to shut down before it is killed
+const CANCEL_TIMEOUT: Duration = Duration::from_secs(5);
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Record Ctrl-C instead of dying, so that a running child can be shut down first
+pub fn catch_interrupt() {
+    let handler: extern "C" fn(libc::c_int) = on_interrupt;
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Recursively watches a source tree for changes
+pub struct SourceWatcher {
+    inotify: Inotify,
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+    ignored: Vec<PathBuf>,
+    buffer: Vec<u8>,
+}
+
+impl SourceWatcher {
+    /// Watch everything below `root`, except for hidden folders and the `ignored` ones
+    pub fn new(root: &Path, ignored: Vec<PathBuf>) -> io::Result<SourceWatcher> {
+        let mut res = SourceWatcher {
+            inotify: Inotify::init()?,
+            dirs: HashMap::new(),
+            ignored,
+            buffer: vec![0; 64 * 1024],
+        };
+        res.add_recursive(root)?;
+        Ok(res)
+    }
+
+    fn add_recursive(&mut self, dir: &Path) -> io::Result<()> {
+        let mask = WatchMask::CLOSE_WRITE
+            | WatchMask::CREATE
+            | WatchMask::DELETE
+            | WatchMask::MODIFY
+            | WatchMask::MOVED_FROM
+            | WatchMask::MOVED_TO;
+        let wd = self.inotify.watches().add(dir, mask)?;
+        self.dirs.insert(wd, dir.to_path_buf());
+        for entry in fs::read_dir(dir)?.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if path.is_dir() && !is_ignored(&self.ignored, &path) {
+                self.add_recursive(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether relevant files changed since the last call, never blocks
+    pub fn changed(&mut self) -> io::Result<bool> {
+        let mut res = false;
+        loop {
+            let events = match self.inotify.read_events(&mut self.buffer) {
+                Ok(events) => events,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(res),
+                Err(e) => return Err(e),
+            };
+            let mut new_dirs = vec![];
+            for event in events {
+                if event.mask.contains(EventMask::Q_OVERFLOW) {
+                    res = true;
+                    continue;
+                }
+                let (Some(dir), Some(name)) = (self.dirs.get(&event.wd), event.name) else {
+                    continue;
+                };
+                let path = dir.join(name);
+                if is_ignored(&self.ignored, &path) {
+                    continue;
+                }
+                if event.mask.contains(EventMask::ISDIR)
+                    && event
+                        .mask
+                        .intersects(EventMask::CREATE | EventMask::MOVED_TO)
+                {
+                    new_dirs.push(path);
+                }
+                res = true;
+            }
+            for dir in new_dirs {
+                // The folder might already be gone again, which is fine
+                let _ = self.add_recursive(&dir);
+            }
+        }
+    }
+}
+
+fn is_ignored(ignored: &[PathBuf], path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    // Hidden files and editor backups
+    name.starts_with('.') || name.ends_with('~') || ignored.iter().any(|i| path.starts_with(i))
+}
+
+/// Spawns the command in its own process group, so that it can be cancelled as a whole
+pub fn spawn_cancellable(cmd: &mut Command) -> io::Result<Child> {
+    cmd.process_group(0).spawn()
+}
+
+/// Interrupts the child and everything it started, like Ctrl-C would
+pub fn cancel(child: &mut Child) -> io::Result<ExitStatus> {
+    let group = -(child.id() as libc::pid_t);
+    unsafe {
+        libc::kill(group, libc::SIGINT);
+    }
+    let start = Instant::now();
+    while start.elapsed() < CANCEL_TIMEOUT {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    unsafe {
+        libc::kill(group, libc::SIGKILL);
+    }
+    child.wait()
+}
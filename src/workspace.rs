@@ -0,0 +1,157 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::package::{Package, MANIFEST_FILENAME};
+
+/// Marker files that exclude a folder (and everything below it) from discovery
+const IGNORE_MARKERS: &[&str] = &["COLCON_IGNORE", "AMENT_IGNORE", "CATKIN_IGNORE"];
+
+pub struct WorkspacePackage {
+    /// Path of the package folder, relative to the workspace root
+    pub path: PathBuf,
+    pub manifest: Package,
+}
+
+/// Index of all packages below `<workspace>/src`, by name
+pub struct Workspace {
+    pub root: PathBuf,
+    pub packages: BTreeMap<String, WorkspacePackage>,
+}
+
+impl Workspace {
+    pub fn discover(root: &Path) -> Workspace {
+        let mut res = Workspace {
+            root: root.to_path_buf(),
+            packages: BTreeMap::new(),
+        };
+        res.crawl(&root.join("src"));
+        res
+    }
+
+    /// Whether there was a source folder to crawl at all
+    ///
+    /// Workspaces with an unusual layout can't be validated, so callers should let colcon decide.
+    pub fn has_sources(&self) -> bool {
+        self.root.join("src").is_dir()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&WorkspacePackage> {
+        self.packages.get(name)
+    }
+
+    /// Whether the package has a build folder in `build_base` (relative to the workspace)
+    pub fn is_built(&self, name: &str, build_base: &str) -> bool {
+        self.root.join(build_base).join(name).is_dir()
+    }
+
+    /// Whether the package has an install folder in `install_base` (relative to the workspace)
+    pub fn is_installed(&self, name: &str, install_base: &str) -> bool {
+        self.root.join(install_base).join(name).is_dir()
+    }
+
+    fn crawl(&mut self, dir: &Path) {
+        if IGNORE_MARKERS.iter().any(|m| dir.join(m).exists()) {
+            return;
+        }
+        // Like colcon, don't look for further packages inside of a package
+        if dir.join(MANIFEST_FILENAME).is_file() {
+            self.add(dir);
+            return;
+        }
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        let mut subdirs = entries
+            .filter_map(|e| e.ok())
+            .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+            .map(|e| e.path())
+            .filter(|p| p.is_dir())
+            .collect::<Vec<PathBuf>>();
+        subdirs.sort();
+        for subdir in subdirs {
+            self.crawl(&subdir);
+        }
+    }
+
+    fn add(&mut self, dir: &Path) {
+        let manifest = match Package::from_dir(dir) {
+            Ok(m) => m,
+            Err(e) => {
+                eprintln!("Warning: Skipping package: {e}");
+                return;
+            }
+        };
+        let path = dir.strip_prefix(&self.root).unwrap_or(dir).to_path_buf();
+        if let Some(existing) = self.packages.get(&manifest.name) {
+            eprintln!(
+                "Warning: Duplicate package '{}' in '{}', using '{}'",
+                manifest.name,
+                path.to_string_lossy(),
+                existing.path.to_string_lossy()
+            );
+            return;
+        }
+        self.packages
+            .insert(manifest.name.clone(), WorkspacePackage { path, manifest });
+    }
+}
+
+/// Whether colcon built into one of the folders of `dir`, whatever its build and install bases are called
+///
+/// colcon marks both bases with `COLCON_IGNORE`. The install base also gets a `.colcon_install_layout`,
+/// and the build folder of every package a `colcon_build.rc`.
+pub fn contains_colcon_base(dir: &Path) -> bool {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return false;
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.join("COLCON_IGNORE").is_file())
+        .any(|base| {
+            base.join(".colcon_install_layout").is_file()
+                || fs::read_dir(&base).is_ok_and(|packages| {
+                    packages
+                        .filter_map(|e| e.ok())
+                        .any(|p| p.path().join("colcon_build.rc").is_file())
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn recognizes_colcon_bases() {
+        let root = env::temp_dir().join(format!("colb-{}-bases", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let build = root.join("build-release");
+        fs::create_dir_all(build.join("foo")).unwrap();
+        fs::create_dir_all(root.join("src").join("ignored_repo")).unwrap();
+        fs::write(
+            root.join("src").join("ignored_repo").join("COLCON_IGNORE"),
+            "",
+        )
+        .unwrap();
+        fs::write(build.join("COLCON_IGNORE"), "").unwrap();
+        // Only marked as ignored so far, which also happens to repositories in the sources
+        assert!(!contains_colcon_base(&root));
+        assert!(!contains_colcon_base(&root.join("src")));
+
+        fs::write(build.join("foo").join("colcon_build.rc"), "0").unwrap();
+        assert!(contains_colcon_base(&root));
+
+        let install = root.join("out").join("install-release");
+        fs::create_dir_all(&install).unwrap();
+        fs::write(install.join("COLCON_IGNORE"), "").unwrap();
+        fs::write(install.join(".colcon_install_layout"), "isolated").unwrap();
+        assert!(contains_colcon_base(&root.join("out")));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}